@@ -1,16 +1,44 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::cmp::Ordering;
 use std::fmt;
+use std::str::FromStr;
+use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
-#[derive(Clone, Eq)]
+// Cards are identified by their suit, rank and the deck they came from, so that
+// identical cards from different decks can be told apart
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Card {
     pub suit: Suit,
     pub rank: Rank,
+    pub deck: u8,
 }
 
 impl Card {
     pub fn new(suit: Suit, rank: Rank) -> Self {
-        Self { suit, rank }
+        Self {
+            suit,
+            rank,
+            deck: 0,
+        }
+    }
+
+    pub fn with_deck(suit: Suit, rank: Rank, deck: u8) -> Self {
+        Self { suit, rank, deck }
+    }
+
+    // Compare cards by rank only, ignoring suit and deck
+    pub fn cmp_rank(&self, other: &Self) -> Ordering {
+        (self.rank as usize).cmp(&(other.rank as usize))
+    }
+
+    // Compare cards by rank, breaking ties by suit for variants that rank the suits
+    pub fn cmp_suit_tie_break(&self, other: &Self) -> Ordering {
+        self.cmp_rank(other).then(
+            self.suit
+                .tie_break_value()
+                .cmp(&other.suit.tie_break_value()),
+        )
     }
 }
 
@@ -47,97 +75,136 @@ impl fmt::Display for Card {
     }
 }
 
-impl Into<String> for Card {
-    fn into(self) -> String {
-        if self.rank == Rank::Joker {
-            let suit_str = match &self.suit {
+impl From<Card> for String {
+    fn from(card: Card) -> Self {
+        if card.rank == Rank::Joker {
+            let suit_str = match &card.suit {
                 Suit::Red => "R",
                 Suit::Black => "B",
                 _ => "",
             };
+            if card.deck > 0 {
+                return format!("{suit_str}O#{}", card.deck);
+            }
             return format!("{suit_str}O");
         } else {
-            let rank_str = match self.rank {
-                Rank::Three => "3",
-                Rank::Four => "4",
-                Rank::Five => "5",
-                Rank::Six => "6",
-                Rank::Seven => "7",
-                Rank::Eight => "8",
-                Rank::Nine => "9",
-                Rank::Ten => "0",
-                Rank::Jack => "J",
-                Rank::Queen => "Q",
-                Rank::King => "K",
-                Rank::Ace => "A",
-                Rank::Two => "2",
-                Rank::Joker => "O",
-            };
-            let suit_str = match &self.suit {
+            let rank_str = card.rank.symbol();
+            let suit_str = match &card.suit {
                 Suit::Spades => "♠",
                 Suit::Diamonds => "♦",
                 Suit::Clubs => "♣",
                 Suit::Hearts => "♥",
                 _ => "",
             };
+            if card.deck > 0 {
+                return format!("{}{}#{}", rank_str, suit_str, card.deck);
+            }
             return format!("{}{}", rank_str, suit_str);
         }
     }
 }
 
-impl From<String> for Card {
-    fn from(value: String) -> Self {
-        let card_str = value.split_at(1);
-        let rank = match card_str.0 {
-            "3" => Rank::Three,
-            "4" => Rank::Four,
-            "5" => Rank::Five,
-            "6" => Rank::Six,
-            "7" => Rank::Seven,
-            "8" => Rank::Eight,
-            "9" => Rank::Nine,
-            "0" => Rank::Ten,
-            "J" => Rank::Jack,
-            "Q" => Rank::Queen,
-            "K" => Rank::King,
-            "A" => Rank::Ace,
-            "2" => Rank::Two,
-            "O" => Rank::Joker,
-            _ => Rank::Three,
+// Parses both the notation cards are sent in (3♥, 0♠, RO) and an ASCII notation that
+// can be typed (3H, TS, 10S, RJ, BJ). Cards from every deck but the first are followed by
+// their deck index, e.g. 3♥#1.
+impl FromStr for Card {
+    type Err = ParseCardError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (value, deck) = match value.split_once('#') {
+            Some((value, deck)) => (
+                value,
+                deck.parse()
+                    .map_err(|_| ParseCardError::InvalidDeck(deck.to_string()))?,
+            ),
+            None => (value, 0),
         };
-        let suit = match card_str.1 {
-            "♠" => Suit::Spades,
-            "♦" => Suit::Diamonds,
-            "♣" => Suit::Clubs,
-            "♥" => Suit::Hearts,
-            "R" => Suit::Red,
-            "B" => Suit::Black,
-            _ => Suit::Red,
+
+        // Jokers are their colour followed by the joker symbol
+        match value.to_uppercase().as_str() {
+            "" => return Err(ParseCardError::Empty),
+            "RO" | "RJ" => return Ok(Card::with_deck(Suit::Red, Rank::Joker, deck)),
+            "BO" | "BJ" => return Ok(Card::with_deck(Suit::Black, Rank::Joker, deck)),
+            _ => {}
+        }
+
+        // The suit is always the last character, and the rank everything before it
+        let suit_char = value.chars().last().unwrap();
+        let (rank_str, suit_str) = value.split_at(value.len() - suit_char.len_utf8());
+        let suit = match suit_str.to_uppercase().as_str() {
+            "♠" | "S" => Suit::Spades,
+            "♦" | "D" => Suit::Diamonds,
+            "♣" | "C" => Suit::Clubs,
+            "♥" | "H" => Suit::Hearts,
+            _ => return Err(ParseCardError::UnknownSuit(suit_str.to_string())),
         };
+        let rank: Rank = rank_str.parse()?;
+        if rank == Rank::Joker {
+            return Err(ParseCardError::UnknownSuit(suit_str.to_string()));
+        }
 
-        Card::new(suit, rank)
+        Ok(Card::with_deck(suit, rank, deck))
     }
 }
 
+// In JSON, cards are written in the same notation as on the native wire
+impl Serialize for Card {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let card_str: String = self.clone().into();
+        serializer.serialize_str(&card_str)
+    }
+}
+
+impl<'de> Deserialize<'de> for Card {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let card_str = String::deserialize(deserializer)?;
+        card_str.parse().map_err(de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseCardError {
+    Empty,
+    UnknownRank(String),
+    UnknownSuit(String),
+    InvalidDeck(String),
+}
+
+impl fmt::Display for ParseCardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseCardError::Empty => write!(f, "no card given"),
+            ParseCardError::UnknownRank(rank) => write!(f, "unknown rank \"{rank}\""),
+            ParseCardError::UnknownSuit(suit) => write!(f, "unknown suit \"{suit}\""),
+            ParseCardError::InvalidDeck(deck) => write!(f, "invalid deck index \"{deck}\""),
+        }
+    }
+}
+
+impl std::error::Error for ParseCardError {}
+
+// Cards are sorted by rank first, then by suit in tie break order and by deck so that the
+// order is total
 impl Ord for Card {
     fn cmp(&self, other: &Self) -> Ordering {
-        (self.rank as usize).cmp(&(other.rank as usize))
+        self.cmp_suit_tie_break(other)
+            .then(self.deck.cmp(&other.deck))
     }
 }
 
 impl PartialOrd for Card {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(&other))
-    }
-}
-
-impl PartialEq for Card {
-    fn eq(&self, other: &Self) -> bool {
-        self.rank == other.rank
+        Some(self.cmp(other))
     }
 }
 
-#[derive(EnumIter, Debug, Clone, PartialEq, Eq, Copy)]
+#[derive(EnumIter, Debug, Clone, PartialEq, Eq, Copy, Hash)]
 pub enum Suit {
     Spades,
     Diamonds,
@@ -147,7 +214,21 @@ pub enum Suit {
     Black,
 }
 
-#[derive(EnumIter, Debug, Clone, PartialEq, Eq, Copy)]
+impl Suit {
+    // Order of the suits when breaking ties, from ♦ up to ♠ and the red joker
+    pub fn tie_break_value(&self) -> usize {
+        match self {
+            Suit::Diamonds => 0,
+            Suit::Clubs => 1,
+            Suit::Hearts => 2,
+            Suit::Spades => 3,
+            Suit::Black => 4,
+            Suit::Red => 5,
+        }
+    }
+}
+
+#[derive(EnumIter, Debug, Clone, PartialEq, Eq, Copy, Hash)]
 pub enum Rank {
     Three = 3,
     Four = 4,
@@ -165,8 +246,125 @@ pub enum Rank {
     Joker = 16,
 }
 
+impl Rank {
+    // Single character used for the rank when sending cards over the network
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Rank::Three => "3",
+            Rank::Four => "4",
+            Rank::Five => "5",
+            Rank::Six => "6",
+            Rank::Seven => "7",
+            Rank::Eight => "8",
+            Rank::Nine => "9",
+            Rank::Ten => "0",
+            Rank::Jack => "J",
+            Rank::Queen => "Q",
+            Rank::King => "K",
+            Rank::Ace => "A",
+            Rank::Two => "2",
+            Rank::Joker => "O",
+        }
+    }
+}
+
+// Accepts the rank symbols cards are sent with, as well as 10 and T for tens
+impl FromStr for Rank {
+    type Err = ParseCardError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_uppercase().as_str() {
+            "10" | "T" => Ok(Rank::Ten),
+            symbol => Rank::iter()
+                .find(|rank| rank.symbol() == symbol)
+                .ok_or_else(|| ParseCardError::UnknownRank(value.to_string())),
+        }
+    }
+}
+
 impl fmt::Display for Rank {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self)
     }
 }
+
+// In JSON, ranks are written as their symbol
+impl Serialize for Rank {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.symbol())
+    }
+}
+
+impl<'de> Deserialize<'de> for Rank {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let rank_str = String::deserialize(deserializer)?;
+        rank_str.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cards_from_different_decks_are_different_cards() {
+        let first = Card::new(Suit::Hearts, Rank::Three);
+        let second = Card::with_deck(Suit::Hearts, Rank::Three, 1);
+        assert!(first != second);
+        assert!(first == Card::with_deck(Suit::Hearts, Rank::Three, 0));
+        assert!(first != Card::new(Suit::Spades, Rank::Three));
+
+        // Sorting keeps the order total, so equal ranks still sort the same way every time
+        assert_eq!(first.cmp(&second), Ordering::Less);
+        assert_eq!(first.cmp_rank(&second), Ordering::Equal);
+    }
+
+    #[test]
+    fn rank_comparison_ignores_suit_and_deck() {
+        let three = Card::new(Suit::Spades, Rank::Three);
+        let two = Card::new(Suit::Diamonds, Rank::Two);
+        let joker = Card::with_deck(Suit::Black, Rank::Joker, 2);
+        assert_eq!(three.cmp_rank(&two), Ordering::Less);
+        assert_eq!(joker.cmp_rank(&two), Ordering::Greater);
+        assert_eq!(
+            three.cmp_rank(&Card::with_deck(Suit::Hearts, Rank::Three, 1)),
+            Ordering::Equal
+        );
+        assert!(three < two && two < joker);
+    }
+
+    #[test]
+    fn suit_tie_break_orders_equal_ranks() {
+        let suits = [Suit::Diamonds, Suit::Clubs, Suit::Hearts, Suit::Spades];
+        for pair in suits.windows(2) {
+            let lower = Card::new(pair[0], Rank::Ace);
+            let higher = Card::new(pair[1], Rank::Ace);
+            assert_eq!(lower.cmp_suit_tie_break(&higher), Ordering::Less);
+            // Sorting puts the suits in the same order
+            assert!(lower < higher);
+        }
+
+        // Rank still comes before suit
+        let ace_of_spades = Card::new(Suit::Spades, Rank::Ace);
+        let two_of_diamonds = Card::new(Suit::Diamonds, Rank::Two);
+        assert_eq!(
+            ace_of_spades.cmp_suit_tie_break(&two_of_diamonds),
+            Ordering::Less
+        );
+
+        // The red joker beats the black one, and the deck makes no difference
+        let black = Card::new(Suit::Black, Rank::Joker);
+        let red = Card::with_deck(Suit::Red, Rank::Joker, 1);
+        assert_eq!(black.cmp_suit_tie_break(&red), Ordering::Less);
+        assert_eq!(
+            red.cmp_suit_tie_break(&Card::new(Suit::Red, Rank::Joker)),
+            Ordering::Equal
+        );
+    }
+}
@@ -1,88 +1,107 @@
-use std::io::{BufRead, BufReader, Read, Write};
 use std::net::{IpAddr, TcpStream};
-use std::str::from_utf8;
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
 use crate::logger::Logger;
+use crate::protocol::{self, Codec, Message, ProtocolError, PROTOCOL_VERSION};
+
+// How often the client tells the server it is still there
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
 
 pub struct Client {
-    tcp_tx: mpsc::Sender<String>,
+    tcp_tx: mpsc::Sender<Message>,
     pub tcp_thread: thread::JoinHandle<()>,
-    stream: Arc<Mutex<TcpStream>>,
+    stream: TcpStream,
+    pub features: Vec<String>, // Optional features agreed on with the server
+    codec: Codec,
     logger: Arc<Mutex<Logger>>,
 }
 
 impl Client {
-    pub fn new(ip: IpAddr, name: String, logger: Arc<Mutex<Logger>>) -> Result<Self, &'static str> {
-        let stream = Arc::new(Mutex::new(TcpStream::connect((ip, 9141)).unwrap()));
-        let mut tcp_stream = stream.lock().unwrap();
+    pub fn new(ip: IpAddr, name: String, logger: Arc<Mutex<Logger>>) -> Result<Self, String> {
+        let mut stream = TcpStream::connect((ip, 9141))
+            .map_err(|e| format!("Could not reach the server: {e}"))?;
         let logger_new = logger.clone();
 
-        tcp_stream.set_nodelay(true);
-        tcp_stream
-            .write_all(format!("name:{name}\0").as_bytes())
-            .unwrap();
-        tcp_stream.flush();
-
-        tcp_stream.set_read_timeout(Some(Duration::from_secs(20)));
-
-        let mut data: [u8; 8] = [0; 8];
-        tcp_stream.read_exact(&mut data);
-        let data = String::from_utf8(data.to_vec()).expect("Invalid Server Response");
+        stream.set_nodelay(true).ok();
+        let hello = Message::Hello {
+            version: PROTOCOL_VERSION,
+            name,
+            features: Vec::new(), // The terminal client sticks to the native encoding
+        };
+        protocol::write_message(&mut stream, &hello)
+            .map_err(|e| format!("Could not send the handshake: {e}"))?;
 
-        if data.contains("err") {
-            return Err("Connection error");
-        } else {
-            logger_new
-                .lock()
-                .unwrap()
-                .log(format!("Server response: {data}"), Duration::new(5, 0));
-        }
-
-        let (tcp_tx, tcp_rx) = mpsc::channel();
+        stream.set_read_timeout(Some(Duration::from_secs(20))).ok();
+        let features = match protocol::read_message(&mut stream) {
+            Ok(Message::Welcome { features }) => features,
+            Ok(Message::Refused(refusal)) => return Err(refusal.to_string()),
+            Ok(_) => {
+                return Err(
+                    "The server sent an unexpected reply. It may be running another version of the game."
+                        .to_string(),
+                )
+            }
+            Err(e) if e.is_timeout() => {
+                return Err(
+                    "The server did not answer the handshake. It may be running another version of the game."
+                        .to_string(),
+                )
+            }
+            Err(e) => return Err(format!("The handshake failed: {e}")),
+        };
+        logger_new.lock().unwrap().log(
+            format!("Server response: connected using protocol version {PROTOCOL_VERSION}"),
+            Duration::new(5, 0),
+        );
+        let codec = Codec::from_features(&features);
+        // Waiting for other players to move can take a while
+        stream.set_read_timeout(None).ok();
 
-        let stream_send_thread = Arc::clone(&stream);
+        let (tcp_tx, tcp_rx) = mpsc::channel::<Message>();
 
+        // Messages are written from their own thread, which sends heartbeats while idle
+        let mut writer = stream
+            .try_clone()
+            .map_err(|e| format!("Connection error: {e}"))?;
         let tcp_thread = thread::spawn(move || loop {
-            let mut tcp_stream = stream_send_thread.lock().unwrap();
-            loop {
-                let message = if let Ok(data) = tcp_rx.try_recv() {
+            let message = match tcp_rx.recv_timeout(HEARTBEAT_INTERVAL) {
+                Ok(message) => {
+                    let message_str = message.encode();
                     logger_new
                         .lock()
                         .unwrap()
-                        .log(format!("Sent: {data}"), Duration::new(5, 0));
-                    data
-                } else {
-                    "ok\0".to_string()
-                };
+                        .log(format!("Sent: {message_str}"), Duration::new(5, 0));
+                    message
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => Message::Heartbeat,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            };
 
-                tcp_stream.write_all(message.as_bytes());
-                tcp_stream.flush();
+            if codec.write(&mut writer, &message).is_err() {
+                break;
             }
         });
 
         Ok(Self {
             tcp_tx,
             tcp_thread,
-            stream: Arc::clone(&stream),
+            stream,
+            features,
+            codec,
             logger,
         })
     }
 
     pub fn send<T>(&mut self, message: T)
     where
-        T: Into<String>,
+        T: Into<Message>,
     {
-        self.tcp_tx.send(message.into());
+        self.tcp_tx.send(message.into()).ok();
     }
-    pub fn read(&mut self) -> Result<String, std::io::Error> {
-        let stream = self.stream.lock().unwrap();
-        let mut buf_reader = BufReader::new(&*stream);
 
-        let mut data = Vec::new();
-        buf_reader.read_until(b'\0', &mut data)?;
-        Ok(from_utf8(&data).unwrap().to_string())
+    pub fn read(&mut self) -> Result<Message, ProtocolError> {
+        self.codec.read(&mut self.stream)
     }
 }
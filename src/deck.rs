@@ -1,8 +1,168 @@
 use crate::card;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
 use strum::IntoEnumIterator;
 
+// Most decks a table can play with, so every deck index fits in a card
+pub const MAX_DECKS: u32 = 8;
+
+// Which cards make up the deck, picked by the host and sent to every client
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Composition {
+    pub num_decks: u32, // 0 for one deck per four players
+    pub jokers: u8,     // Jokers per deck, 0 to 2
+    pub removed_ranks: Vec<card::Rank>,
+    pub leftover: Leftover,
+}
+
+// What happens to the cards left over when the deck does not split evenly between players
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Leftover {
+    SetAside, // Put aside face down
+    Shown,    // Put aside face up for everyone to see
+}
+
+impl Composition {
+    pub fn new() -> Self {
+        Self {
+            num_decks: 0,
+            jokers: 2,
+            removed_ranks: Vec::new(),
+            leftover: Leftover::SetAside,
+        }
+    }
+
+    // Number of decks to play with at a table
+    pub fn decks_for(&self, num_players: usize) -> u32 {
+        if self.num_decks == 0 {
+            (num_players / 4 + 1).min(MAX_DECKS as usize) as u32
+        } else {
+            self.num_decks
+        }
+    }
+
+    pub fn cards_per_deck(&self) -> usize {
+        let ranks = card::Rank::iter()
+            .filter(|rank| *rank != card::Rank::Joker && !self.removed_ranks.contains(rank))
+            .count();
+        4 * ranks + self.jokers.min(2) as usize
+    }
+
+    // Whether the composition makes a deck at all, checked when the host enters it
+    pub fn check(&self) -> Result<(), String> {
+        if self.num_decks > MAX_DECKS {
+            return Err(format!("A table can play with at most {MAX_DECKS} decks."));
+        }
+        if self.jokers > 2 {
+            return Err("A deck has at most 2 jokers.".to_string());
+        }
+        if self.cards_per_deck() == 0 {
+            return Err("The deck needs at least one card.".to_string());
+        }
+        Ok(())
+    }
+
+    // Whether the deck deals every player at the table at least one card
+    pub fn check_table(&self, num_players: usize) -> Result<(), String> {
+        self.check()?;
+        let cards = self.cards_per_deck() * self.decks_for(num_players) as usize;
+        if cards < num_players {
+            return Err(format!(
+                "{cards} card(s) are not enough to deal one to each of {num_players} players."
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Composition {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for Composition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let removed: Vec<String> = self.removed_ranks.iter().map(|r| r.to_string()).collect();
+        write!(
+            f,
+            "{} deck(s), {} joker(s) per deck, removed ranks: [{}], leftover cards: {:?}",
+            self.num_decks,
+            self.jokers,
+            removed.join(", "),
+            self.leftover
+        )
+    }
+}
+
+impl From<Composition> for String {
+    fn from(composition: Composition) -> Self {
+        let leftover = match composition.leftover {
+            Leftover::SetAside => "0",
+            Leftover::Shown => "1",
+        };
+        let mut fields = vec![
+            composition.num_decks.to_string(),
+            composition.jokers.to_string(),
+            leftover.to_string(),
+        ];
+        fields.extend(
+            composition
+                .removed_ranks
+                .iter()
+                .map(|r| r.symbol().to_string()),
+        );
+        fields.join(" ")
+    }
+}
+
+impl FromStr for Composition {
+    type Err = ParseCompositionError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        // Fall back to the default composition for anything missing, but refuse anything
+        // unreadable
+        let mut composition = Composition::new();
+        let mut value = value.split_whitespace();
+        let unreadable = |s: &str| ParseCompositionError(s.to_string());
+        if let Some(num_decks) = value.next() {
+            composition.num_decks = num_decks.parse().map_err(|_| unreadable(num_decks))?;
+        }
+        if let Some(jokers) = value.next() {
+            composition.jokers = jokers.parse().map_err(|_| unreadable(jokers))?;
+        }
+        composition.leftover = match value.next() {
+            None | Some("0") => Leftover::SetAside,
+            Some("1") => Leftover::Shown,
+            Some(leftover) => return Err(unreadable(leftover)),
+        };
+        composition.removed_ranks = value
+            .map(|s| s.parse().map_err(|_| unreadable(s)))
+            .collect::<Result<_, _>>()?;
+        Ok(composition)
+    }
+}
+
+// A field of the composition that could not be read
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseCompositionError(pub String);
+
+impl fmt::Display for ParseCompositionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unreadable composition: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseCompositionError {}
+
 pub struct Deck {
     pub cards: Vec<card::Card>,
+    pub composition: Composition,
 }
 
 impl Deck {
@@ -10,29 +170,78 @@ impl Deck {
         self.cards.push(c);
     }
 
+    // Standard decks with both jokers
     pub fn new(n: u32) -> Self {
+        let mut composition = Composition::new();
+        composition.num_decks = n;
+        Self::with_composition(composition)
+    }
+
+    pub fn with_composition(composition: Composition) -> Self {
         let mut cards = Vec::new();
-        for _ in 0u32..n {
-            // Iterate through possible cards, minus Jokers
+        for deck in 0..composition.num_decks.min(MAX_DECKS) {
+            // Index each deck so identical cards from different decks can be told apart
+            let deck = deck as u8;
+            // Iterate through possible cards, minus Jokers and removed ranks
             for suit in card::Suit::iter() {
                 if suit != card::Suit::Red && suit != card::Suit::Black {
                     for rank in card::Rank::iter() {
-                        if rank != card::Rank::Joker {
-                            cards.push(card::Card::new(suit.clone(), rank.clone()))
+                        if rank != card::Rank::Joker && !composition.removed_ranks.contains(&rank) {
+                            cards.push(card::Card::with_deck(suit, rank, deck))
                         }
                     }
                 }
             }
-            // Add Jokers
-            cards.push(card::Card::new(card::Suit::Red.clone(), card::Rank::Joker.clone()));
-            cards.push(card::Card::new(card::Suit::Black.clone(), card::Rank::Joker.clone()));
+            // Add Jokers, the red one first
+            for suit in [card::Suit::Red, card::Suit::Black]
+                .into_iter()
+                .take(composition.jokers as usize)
+            {
+                cards.push(card::Card::with_deck(suit, card::Rank::Joker, deck));
+            }
         }
-        Self { cards }
+        Self { cards, composition }
     }
 
+    // Put every card back into the deck before dealing a new hand
+    pub fn reset(&mut self) {
+        *self = Deck::with_composition(self.composition.clone());
+    }
+
+    // Fisher–Yates shuffle, so every order of the deck is equally likely
+    pub fn shuffle<R>(&mut self, rng: &mut R)
+    where
+        R: Rng + ?Sized,
+    {
+        for i in (1..self.cards.len()).rev() {
+            let j = rng.gen_range(0..=i);
+            self.cards.swap(i, j);
+        }
+    }
+
+    // Shuffle with a generator that gives the same order for the same seed on every platform
+    pub fn shuffle_with_seed(&mut self, seed: u64) {
+        self.shuffle(&mut ChaCha8Rng::seed_from_u64(seed));
+    }
+
+    // Deal the deck one card at a time around the table. Cards that would leave the hands
+    // uneven are left in the deck.
+    pub fn deal(&mut self, num_players: usize) -> Vec<Vec<card::Card>> {
+        let mut hands = vec![Vec::new(); num_players];
+        let rounds = self.cards.len().checked_div(num_players).unwrap_or(0);
+        for _ in 0..rounds {
+            for hand in &mut hands {
+                hand.push(self.draw_card());
+            }
+        }
+        hands
+    }
+
+    // Draw from the top of the deck, which should be shuffled first
     pub fn draw_card(&mut self) -> card::Card {
-        let index = (rand::random::<f32>() * self.cards.len() as f32).floor() as usize;
-        self.cards.remove( index )
+        self.cards
+            .pop()
+            .expect("Should not draw from an empty deck")
     }
 
     pub fn is_empty(&mut self) -> bool {
@@ -43,3 +252,92 @@ impl Deck {
         self.cards.sort_unstable();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_deals_the_same_order() {
+        let mut first = Deck::new(2);
+        let mut second = Deck::new(2);
+        first.shuffle_with_seed(9141);
+        second.shuffle_with_seed(9141);
+        assert!(first.cards == second.cards);
+
+        let mut other = Deck::new(2);
+        other.shuffle_with_seed(9142);
+        assert!(first.cards != other.cards);
+    }
+
+    #[test]
+    fn composition_removes_ranks_and_jokers() {
+        let composition = Composition {
+            num_decks: 2,
+            jokers: 1,
+            removed_ranks: vec![card::Rank::Three],
+            leftover: Leftover::SetAside,
+        };
+        let mut deck = Deck::with_composition(composition);
+        assert_eq!(deck.cards.len(), 2 * (48 + 1));
+        assert!(deck
+            .cards
+            .iter()
+            .all(|card| card.rank != card::Rank::Three && card.suit != card::Suit::Black));
+
+        // 98 cards between 4 players leaves 2 over
+        let hands = deck.deal(4);
+        assert!(hands.iter().all(|hand| hand.len() == 24));
+        assert_eq!(deck.cards.len(), 2);
+    }
+
+    #[test]
+    fn composition_is_read_back_or_refused() {
+        let composition = Composition {
+            num_decks: 2,
+            jokers: 1,
+            removed_ranks: vec![card::Rank::Three, card::Rank::Four],
+            leftover: Leftover::Shown,
+        };
+        let composition_str: String = composition.clone().into();
+        assert_eq!(composition_str.parse(), Ok(composition));
+
+        assert_eq!("".parse(), Ok(Composition::new()));
+        assert!("two".parse::<Composition>().is_err());
+        assert!("1 2 0 Q?".parse::<Composition>().is_err());
+    }
+
+    #[test]
+    fn composition_has_to_deal_every_player_a_card() {
+        let mut composition = Composition::new();
+        composition.num_decks = MAX_DECKS + 1;
+        assert!(composition.check().is_err());
+
+        // Only the jokers are left
+        composition.num_decks = 1;
+        composition.removed_ranks = card::Rank::iter()
+            .filter(|rank| *rank != card::Rank::Joker)
+            .collect();
+        assert!(composition.check().is_ok());
+        assert!(composition.check_table(2).is_ok());
+        assert!(composition.check_table(3).is_err());
+
+        composition.jokers = 0;
+        assert!(composition.check().is_err());
+
+        // Nobody to deal to
+        assert!(Deck::new(1).deal(0).is_empty());
+    }
+
+    #[test]
+    fn shuffle_keeps_every_card() {
+        let mut deck = Deck::new(1);
+        deck.shuffle_with_seed(7);
+        assert!(deck.cards != Deck::new(1).cards);
+
+        deck.sort();
+        let mut sorted = Deck::new(1);
+        sorted.sort();
+        assert!(deck.cards == sorted.cards);
+    }
+}
@@ -2,6 +2,8 @@ use crate::card;
 use crate::hand;
 use crate::play;
 use crate::player;
+use crate::player_client::PlayerClient;
+use crate::server::Connection;
 
 use crossterm::cursor::SetCursorStyle;
 use crossterm::event::{poll, read, Event, KeyCode, KeyEvent, KeyEventKind};
@@ -15,7 +17,6 @@ use std::any::Any;
 use std::collections::VecDeque;
 use std::io::stdout;
 use std::io::{self, Write};
-use std::net::TcpStream;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::time::Duration;
@@ -400,6 +401,9 @@ pub fn get_keystate() -> Input_States {
                 KeyCode::Char(' ') => {
                     return Input_States::Space;
                 }
+                KeyCode::Tab | KeyCode::Char('h') => {
+                    return Input_States::Hint;
+                }
 
                 _ => {
                     return Input_States::Empty;
@@ -492,7 +496,7 @@ pub fn player_note(a: String, height: u16) {
     io::stdout().flush();
 }
 
-pub fn show_server_status(players_streams: &VecDeque<(player::Player, TcpStream, Instant)>) {
+pub fn show_server_status(players_streams: &VecDeque<(player::Player, Connection, Instant)>) {
     for i in terminal::size().unwrap().0 / 3..(terminal::size().unwrap().0 * 2) / 3 {
         for j in terminal::size().unwrap().1 / 3..terminal::size().unwrap().1 * 2 / 3 {
             queue!(
@@ -523,7 +527,11 @@ pub fn show_server_status(players_streams: &VecDeque<(player::Player, TcpStream,
             SetForegroundColor(Color::White)
         );
         let name = &player.0.name;
-        let ip = &player.1.peer_addr().unwrap_or("0.0.0.0:0".parse().unwrap());
+        let ip = &player
+            .1
+            .stream
+            .peer_addr()
+            .unwrap_or("0.0.0.0:0".parse().unwrap());
         let time = &player.2.elapsed().as_millis();
         queue!(
             io::stdout(),
@@ -536,6 +544,62 @@ pub fn show_server_status(players_streams: &VecDeque<(player::Player, TcpStream,
     stdout().flush();
 }
 
+// Scores, card counts and clocks in the top right corner, highest score first. The player's teammate
+// is shown in a different color.
+pub fn show_players(players: &VecDeque<PlayerClient>, player: &player::Player) {
+    let mut players: Vec<&PlayerClient> = players.iter().collect();
+    players.sort_by_key(|player| std::cmp::Reverse(player.score));
+
+    let lines: Vec<String> = players
+        .iter()
+        .map(|player| match player.clock {
+            Some(clock) => {
+                let secs = clock.as_secs();
+                format!(
+                    "{} {} [{}] {}:{:02}",
+                    player.name,
+                    player.score,
+                    player.num_cards,
+                    secs / 60,
+                    secs % 60
+                )
+            }
+            None => format!("{} {} [{}]", player.name, player.score, player.num_cards),
+        })
+        .collect();
+    let width = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max("Scores".len()) as u16;
+    let left = terminal::size().unwrap().0.saturating_sub(width + 2);
+
+    queue!(
+        io::stdout(),
+        cursor::MoveTo(left, 1),
+        style::SetBackgroundColor(Color::Black),
+        style::PrintStyledContent(format!("{:<1$}", "Scores", width as usize).yellow())
+    );
+    for (i, (line, other)) in lines.iter().zip(&players).enumerate() {
+        let line = format!("{:<1$}", line, width as usize);
+        let is_teammate =
+            other.name != player.name && other.team.is_some() && other.team == player.team;
+        queue!(
+            io::stdout(),
+            cursor::MoveTo(left, 2 + i as u16),
+            style::SetBackgroundColor(Color::Black),
+            style::PrintStyledContent(if is_teammate {
+                line.cyan()
+            } else {
+                line.white()
+            })
+        );
+    }
+
+    stdout().flush();
+}
+
 pub fn show_play(p: Option<&play::Play>) {
     //clear center of screen
     for i in terminal::size().unwrap().0 / 3..(terminal::size().unwrap().0 * 2) / 3 {
@@ -681,6 +745,23 @@ pub fn show_play(p: Option<&play::Play>) {
 }
 
 //TODO maybe see if for selected cards, we can use references instead of indices
+// Seconds left to move, shown to the right of the hand
+pub fn show_countdown(time_left: Duration) {
+    let secs = time_left.as_secs_f32().ceil() as u64;
+    let text = format!("{secs:>3}s");
+    let colored = if secs <= 5 { text.red() } else { text.white() };
+    queue!(
+        io::stdout(),
+        cursor::MoveTo(
+            terminal::size().unwrap().0.saturating_sub(8),
+            terminal::size().unwrap().1 - 2
+        ),
+        style::SetBackgroundColor(Color::Black),
+        style::PrintStyledContent(colored)
+    );
+    io::stdout().flush();
+}
+
 pub fn show_hand(hand: &hand::Hand, selected: &Vec<bool>, selector: usize) {
     //clear bottom of screen
     for i in 0..terminal::size().unwrap().0 {
@@ -851,5 +932,6 @@ pub enum Input_States {
     Up,
     Down,
     Space,
+    Hint,
     Empty,
 }
@@ -0,0 +1,156 @@
+use crate::card::Card;
+use crate::deck::{Composition, Deck};
+use crate::hand::Hand;
+use crate::play::Play;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+// Commit–reveal for the deal. Before every hand, the server publishes the hash of a secret it
+// picked, and only then do the clients send it fresh entropy. The deck is shuffled with a seed
+// mixed from the secret and that entropy, so the host cannot try secrets until one deals the
+// hands it wants. Once the hand is over the secret is revealed, and clients can check that their
+// entropy went into the deal and that the deal they were given is the one the seed produces.
+// This does not stop a host that also runs a player from picking that player's entropy after
+// seeing everyone else's.
+
+pub fn new_secret() -> [u8; 32] {
+    rand::random()
+}
+
+// Hash of the secret, published before dealing
+pub fn commit(secret: &[u8]) -> String {
+    to_hex(&Sha256::digest(secret))
+}
+
+// Seed the deck is shuffled with, from the secret and the clients' entropy in seating order
+pub fn deal_seed(secret: &[u8], entropy: &[u64]) -> u64 {
+    let mut hasher = Sha256::new();
+    hasher.update(secret);
+    for value in entropy {
+        hasher.update(value.to_le_bytes());
+    }
+    let hash = hasher.finalize();
+    u64::from_le_bytes(hash[..8].try_into().unwrap())
+}
+
+// The hands a seed deals, in dealing order
+pub fn deal(composition: &Composition, seed: u64, num_players: usize) -> Vec<Vec<Card>> {
+    let mut deck = Deck::with_composition(composition.clone());
+    deck.shuffle_with_seed(seed);
+    deck.deal(num_players)
+}
+
+// What a client records during a hand, to check the deal once the secret is revealed
+#[derive(Default)]
+pub struct DealRecord {
+    pub commitment: String,
+    pub contribution: Option<u64>, // Entropy this client sent for the deal
+    pub entropy: Vec<(String, u64)>, // Entropy the server mixed into the deal, in seating order
+    pub transfers: Vec<(String, String, Vec<Card>)>, // Cards given between players, e.g. tribute
+    pub first_round: usize,        // Index of the hand's first round in the client's rounds
+}
+
+impl DealRecord {
+    // Check the revealed secret against the commitment, then replay the hand from the deal it
+    // produces. Every card given or played has to come from the hand of the player using it, and
+    // the cards left in the player's own hand have to match. Returns the seed of the deal, so it
+    // can be reproduced.
+    pub fn verify(
+        &self,
+        secret: &[u8],
+        composition: &Composition,
+        order: &[String],
+        plays: &[&Play],
+        player_name: &str,
+        hand: &Hand,
+    ) -> Result<u64, String> {
+        if commit(secret) != self.commitment {
+            return Err("the revealed secret does not match the commitment".to_string());
+        }
+        if let Some(contribution) = self.contribution {
+            if !self
+                .entropy
+                .contains(&(player_name.to_string(), contribution))
+            {
+                return Err("your entropy was left out of the deal".to_string());
+            }
+        }
+
+        let entropy: Vec<u64> = self.entropy.iter().map(|(_, value)| *value).collect();
+        let seed = deal_seed(secret, &entropy);
+        let mut hands: HashMap<&str, Hand> = order
+            .iter()
+            .map(String::as_str)
+            .zip(deal(composition, seed, order.len()))
+            .map(|(name, cards)| (name, Hand { cards }))
+            .collect();
+
+        for (from_name, to_name, cards) in &self.transfers {
+            match hands.get_mut(from_name.as_str()) {
+                Some(from) if from.contains_cards(cards) => from.remove_cards(cards),
+                _ => return Err(format!("{from_name} gave cards they were not dealt")),
+            }
+            match hands.get_mut(to_name.as_str()) {
+                Some(to) => to.cards.extend(cards.iter().cloned()),
+                None => return Err(format!("{to_name} was not dealt in")),
+            }
+        }
+
+        for play in plays {
+            let name = play.player.name.as_str();
+            match hands.get_mut(name) {
+                Some(dealt) if dealt.contains_cards(&play.cards) => dealt.remove_cards(&play.cards),
+                _ => return Err(format!("{name} played cards they were not dealt")),
+            }
+        }
+
+        let mut expected = hands
+            .remove(player_name)
+            .ok_or_else(|| "you were not dealt in".to_string())?;
+        let mut held = hand.clone();
+        expected.sort();
+        held.sort();
+        if expected != held {
+            return Err("your hand does not match the deal".to_string());
+        }
+        Ok(seed)
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn revealed_secret_matches_commitment() {
+        let secret = new_secret();
+        let commitment = commit(&secret);
+        let revealed = from_hex(&to_hex(&secret)).unwrap();
+        assert_eq!(commit(&revealed), commitment);
+        assert_ne!(commit(&new_secret()), commitment);
+    }
+
+    #[test]
+    fn entropy_changes_the_deal() {
+        let secret = new_secret();
+        let seed = deal_seed(&secret, &[1, 2, 3, 4]);
+        assert_eq!(seed, deal_seed(&secret, &[1, 2, 3, 4]));
+        assert_ne!(seed, deal_seed(&secret, &[1, 2, 3, 5]));
+        let composition = Deck::new(1).composition;
+        assert!(deal(&composition, seed, 4) == deal(&composition, seed, 4));
+    }
+}
@@ -1,16 +1,21 @@
 use crate::card;
 use crate::client::Client;
+use crate::deck::Composition;
 use crate::display::Display;
 use crate::display::{self, Warning};
+use crate::fair_deal::DealRecord;
+use crate::hand::Hand;
 use crate::logger::Logger;
 use crate::play;
 use crate::player::Player;
 use crate::player_client::PlayerClient;
+use crate::protocol::Message;
 use crate::round;
 use crate::round::Round;
+use crate::rules::RuleSet;
 use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 pub struct GameClient {
     pub player: Player,
@@ -19,6 +24,9 @@ pub struct GameClient {
     pub rounds: Vec<round::Round>,
     pub logger: Arc<Mutex<Logger>>,
     pub display: Display,
+    pub rules: RuleSet,
+    pub deal_record: DealRecord,
+    pub composition: Composition,
 }
 
 impl GameClient {
@@ -37,6 +45,9 @@ impl GameClient {
             rounds,
             logger,
             display,
+            rules: RuleSet::new(),
+            deal_record: DealRecord::default(),
+            composition: Composition::new(),
         }
     }
 
@@ -44,61 +55,269 @@ impl GameClient {
     pub fn wait_for_start(&mut self) {
         loop {
             let incoming_message = self.client.read().unwrap();
-            match incoming_message.as_str() {
-                "s\0" => {
-                    self.logger
-                        .lock()
-                        .unwrap()
-                        .log("Game started", Duration::ZERO);
-                    self.play();
-                }
-                _ => {}
+            if let Message::Deal { seats, hand } = incoming_message {
+                self.logger
+                    .lock()
+                    .unwrap()
+                    .log("Game started", Duration::ZERO);
+                self.start_hand(seats, hand);
+                self.play();
+                return;
             }
+            self.handle_message(incoming_message);
         }
     }
 
     pub fn play(&mut self) {
         loop {
             let incoming_message = self.client.read().unwrap();
-            match incoming_message.as_str() {
-                "r\0" => {
-                    self.play_round();
+            if !self.handle_message(incoming_message) {
+                break;
+            }
+        }
+    }
+
+    // Handle a message from the server outside of a round. Returns false once the match is over.
+    fn handle_message(&mut self, message: Message) -> bool {
+        match message {
+            // House rules picked by the host
+            Message::Rules(rules) => {
+                self.rules = rules;
+                self.logger
+                    .lock()
+                    .unwrap()
+                    .log(format!("Rules: {rules}"), Duration::ZERO);
+            }
+            // A new hand was dealt
+            Message::Deal { seats, hand } => {
+                self.start_hand(seats, hand);
+            }
+            Message::NewRound => {
+                self.play_round();
+            }
+            // Final standings of the hand, from first to last place
+            Message::Standings(standings) => {
+                for (place, name) in standings.iter().enumerate() {
+                    self.logger
+                        .lock()
+                        .unwrap()
+                        .log(format!("{}. {name}", place + 1), Duration::ZERO);
                 }
-                _ => {}
+                // Any cards left over go back to the deck
+                self.player.hand = Hand::new();
             }
+            // Scoreboard after every hand
+            Message::Scores(scores) => {
+                for (name, score) in scores {
+                    if name == self.player.name {
+                        self.player.score = score;
+                    }
+                    self.player_client(&name).score = score;
+                }
+                display::show_players(&self.players, &self.player);
+            }
+            // Commitment to the next deal, answered with fresh entropy to mix into it
+            Message::Commitment(commitment) => {
+                let contribution = rand::random();
+                self.client.send(Message::Entropy(contribution));
+                self.deal_record = DealRecord {
+                    commitment,
+                    contribution: Some(contribution),
+                    entropy: Vec::new(),
+                    transfers: Vec::new(),
+                    first_round: self.rounds.len(),
+                };
+            }
+            // The entropy of every player the server mixed into the deal
+            Message::DealEntropy(entropy) => {
+                self.deal_record.entropy = entropy;
+            }
+            // Which cards the deck is made of
+            Message::Composition(composition) => {
+                self.logger
+                    .lock()
+                    .unwrap()
+                    .log(format!("Deck: {composition}"), Duration::ZERO);
+                self.composition = composition;
+            }
+            // Cards left over from the deal, listed if they are shown to everyone
+            Message::Leftover { count, cards } => {
+                let cards: Vec<String> = cards.iter().map(|card| card.to_string()).collect();
+                let shown = if cards.is_empty() {
+                    String::new()
+                } else {
+                    format!(": {}", cards.join(" "))
+                };
+                self.logger.lock().unwrap().log(
+                    format!("{count} leftover card(s) put aside{shown}"),
+                    Duration::ZERO,
+                );
+            }
+            // The secret behind the commitment, revealed once the hand is over
+            Message::Reveal { secret, order } => {
+                self.verify_deal(&secret, &order);
+            }
+            // Who leads the hand, and the card that gave them the lead
+            Message::Leader { name, card } => {
+                let opening = match card {
+                    Some(card) => format!(" with the {card}"),
+                    None => String::new(),
+                };
+                self.logger
+                    .lock()
+                    .unwrap()
+                    .log(format!("{name} leads the hand{opening}."), Duration::ZERO);
+            }
+            // Partnerships, when playing in teams
+            Message::Teams(teams) => {
+                for (name, team) in teams {
+                    if name == self.player.name {
+                        self.player.team = Some(team);
+                    }
+                    self.player_client(&name).team = Some(team);
+                }
+                display::show_players(&self.players, &self.player);
+            }
+            Message::CardCounts(counts) => {
+                self.update_counts(counts);
+            }
+            Message::Clocks(clocks) => {
+                self.update_clocks(clocks);
+            }
+            // The match is over
+            Message::GameEnd { winner } => {
+                self.logger.lock().unwrap().log(
+                    format!("{winner} won the match. Congratulations"),
+                    Duration::ZERO,
+                );
+                return false;
+            }
+            // Cards given from one player to another during the tribute
+            Message::Tribute { from, to, cards } => {
+                if cards.is_empty() {
+                    self.logger.lock().unwrap().log(
+                        format!("{from} holds both jokers and does not pay tribute."),
+                        Duration::ZERO,
+                    );
+                    return true;
+                }
+                if from == self.player.name {
+                    self.player.hand.remove_cards(&cards);
+                }
+                if to == self.player.name {
+                    for card in &cards {
+                        self.player.hand.add_card(card.clone());
+                    }
+                    self.player.hand.sort();
+                }
+                let cards_str: Vec<String> = cards.iter().map(|card| card.to_string()).collect();
+                let cards_str = cards_str.join(" ");
+                self.logger
+                    .lock()
+                    .unwrap()
+                    .log(format!("{from} gave {cards_str} to {to}."), Duration::ZERO);
+                self.deal_record.transfers.push((from, to, cards));
+            }
+            // Asked to give cards back to the player who paid tribute
+            Message::AskTribute { count, to } => {
+                self.return_tribute(count, to);
+            }
+            Message::Reject(rejection) => {
+                self.show_rejection(rejection);
+            }
+            _ => {}
         }
+        true
+    }
+
+    // Take the dealt hand, the seating order and everyone's card count
+    fn start_hand(&mut self, seats: Vec<(String, usize)>, hand: Vec<card::Card>) {
+        self.player.hand = Hand::new();
+        for card in hand {
+            self.player.hand.add_card(card);
+        }
+        self.player.hand.sort();
+
+        // Seat the players in order, keeping what is already known about them
+        let mut seated = VecDeque::new();
+        for (name, num_cards) in seats {
+            let mut player = self
+                .players
+                .iter()
+                .find(|p| p.name == name)
+                .cloned()
+                .unwrap_or_else(|| PlayerClient::new(name));
+            player.num_cards = num_cards as u16;
+            seated.push_back(player);
+        }
+        self.players = seated;
+        display::show_players(&self.players, &self.player);
+    }
+
+    // Number of cards every player holds
+    fn update_counts(&mut self, counts: Vec<(String, usize)>) {
+        for (name, num_cards) in counts {
+            self.player_client(&name).num_cards = num_cards as u16;
+        }
+        display::show_players(&self.players, &self.player);
     }
 
     pub fn play_round(&mut self) {
         let mut round = Round::new();
         loop {
-            let message = self.client.read().unwrap();
-            if message.len() < 4 {
-                // Handle error: message too short
-                continue;
-            }
-            let (id, message) = message.split_at(4);
-            match id {
-                "m" => {
-                    self.play_move(&mut round);
-                }
-                "p" => round.add_play(message.to_string().into()),
-                "e" => break,
+            match self.client.read().unwrap() {
+                // The prompt carries the time left to move, if there is a limit
+                Message::Prompt { time_left } => {
+                    let deadline = time_left.map(|time_left| Instant::now() + time_left);
+                    self.play_move(&mut round, deadline);
+                }
+                Message::Play(play) => {
+                    let play = with_table_rules(play, self.rules);
+                    // Only remove our cards once the server has accepted the play
+                    if play.player == self.player {
+                        self.player.hand.remove_cards(&play.cards);
+                    }
+                    round.add_play(play);
+                }
+                Message::Pass(name) => {
+                    round.add_play(play::Play::pass(Player::new(name)));
+                }
+                Message::Reject(rejection) => {
+                    self.show_rejection(rejection);
+                }
+                // A player went out, along with the place they finished in
+                Message::WentOut { place, name } => {
+                    self.logger
+                        .lock()
+                        .unwrap()
+                        .log(format!("{name} finished in place {place}."), Duration::ZERO);
+                }
+                Message::Clocks(clocks) => self.update_clocks(clocks),
+                Message::CardCounts(counts) => self.update_counts(counts),
+                Message::Forfeit(player_name) => {
+                    self.logger.lock().unwrap().log(
+                        format!("{player_name} ran out of time and forfeits the hand."),
+                        Duration::ZERO,
+                    );
+                }
+                Message::RoundEnd => break,
                 _ => {}
             }
         }
 
-        let winner = &round.plays.last().unwrap().player;
-        let winner_name = &winner.name;
-        self.logger.lock().unwrap().log(
-            format!("{winner_name} won the round. They will start the next round."),
-            Duration::ZERO,
-        );
+        // The round can end without any play if the leader forfeits
+        if let Some(winner) = round.winner() {
+            let winner_name = &winner.name;
+            self.logger
+                .lock()
+                .unwrap()
+                .log(format!("{winner_name} won the round."), Duration::ZERO);
+        }
 
         self.rounds.push(round);
     }
 
-    pub fn play_move<T>(&mut self, mut round: T)
+    pub fn play_move<T>(&mut self, mut round: T, deadline: Option<Instant>)
     where
         T: AsMut<Round>,
     {
@@ -107,19 +326,44 @@ impl GameClient {
         let mut selected = vec![false; hand_size]; // array to represent card selection
         let mut selector = 0usize; // cursor to create selection
 
-        let mut current_play: Option<play::Class> = Some(play::Class::Invalid);
-        let mut play_rank: Option<card::Rank> = Some(card::Rank::Three);
+        // legal plays to cycle through when asking for a hint
+        let hints = play::legal_plays(&self.player.hand, round.last_play(), &self.rules);
+        let mut hint_index = 0usize;
 
         display::show_hand(&self.player.hand, &selected, selector);
 
-        display::show_play(round.plays.last());
+        display::show_play(round.last_play());
 
         // card selection to be inputted into play
         loop {
+            // Once time runs out the server moves for the player
+            if let Some(deadline) = deadline {
+                let time_left = deadline.saturating_duration_since(Instant::now());
+                display::show_countdown(time_left);
+                if time_left.is_zero() {
+                    self.display
+                        .add_renderable(Arc::new(Mutex::new(Warning::new(
+                            "Out of time.",
+                            Duration::new(5, 0),
+                        ))));
+                    break;
+                }
+            }
+
             let current_state = display::get_keystate();
             match current_state {
                 display::Input_States::Esc => {
-                    break;
+                    // Pass the turn, unless leading the round
+                    if round.last_play().is_none() {
+                        self.display
+                            .add_renderable(Arc::new(Mutex::new(Warning::new(
+                                play::Rejection::CannotPass.to_string(),
+                                Duration::new(5, 0),
+                            ))))
+                    } else {
+                        self.client.send(play::Play::pass(self.player.clone()));
+                        break;
+                    }
                 }
                 display::Input_States::Right => {
                     selector = (selector + 1) % self.player.hand.cards.len();
@@ -132,9 +376,35 @@ impl GameClient {
                 display::Input_States::Space => {
                     selected[selector] = !selected[selector];
                 }
+                display::Input_States::Hint => {
+                    if hints.is_empty() {
+                        self.display
+                            .add_renderable(Arc::new(Mutex::new(Warning::new(
+                                "No playable combinations, press Esc to pass.",
+                                Duration::new(5, 0),
+                            ))))
+                    } else {
+                        // Select the cards of the next hint
+                        selected = vec![false; hand_size];
+                        for hint_card in &hints[hint_index] {
+                            if let Some(index) = self
+                                .player
+                                .hand
+                                .cards
+                                .iter()
+                                .position(|card| card == hint_card)
+                            {
+                                selected[index] = true;
+                            }
+                        }
+                        selector = selected.iter().position(|&b| b).unwrap_or(selector);
+                        hint_index = (hint_index + 1) % hints.len();
+                    }
+                }
                 display::Input_States::Enter => {
+                    let mut player_move = self.selected_play(&selected);
                     // Play selected play
-                    if current_play == Some(play::Class::Invalid) {
+                    if !selected.contains(&true) || player_move.class == play::Class::Invalid {
                         self.display
                             .add_renderable(Arc::new(Mutex::new(Warning::new(
                                 "Please make a valid move.",
@@ -142,28 +412,11 @@ impl GameClient {
                             ))))
                     }
                     // Allow user to play card if empty round, or valid move
-                    else if round.plays.is_empty()
-                        || (current_play == Some(round.plays.last().unwrap().class)
-                            && play_rank.unwrap() as usize
-                                > round.plays.last().unwrap().rank as usize)
+                    else if round.last_play().is_none()
+                        || player_move.resolve_against(round.last_play().unwrap())
                     {
-                        let mut index = 0usize;
-
-                        let mut move_cards = Vec::new();
-
-                        while index < self.player.hand.cards.len() {
-                            if selected[index] {
-                                move_cards.push(self.player.hand.cards.remove(index));
-                                selected.remove(index);
-                            } else {
-                                index += 1;
-                            }
-                        }
-                        let mut player_move = play::Play::new(self.player.clone());
-                        player_move.set_cards(move_cards);
+                        // Cards stay in the hand until the server accepts the play
                         self.client.send(player_move);
-
-                        // TODO: Send the move back here
                         break;
                     } else {
                         self.display
@@ -178,33 +431,179 @@ impl GameClient {
                 }
             }
 
-            // Gather currently selected cards
-            let mut selected_cards = Vec::new();
+            // Display the player's hand
+            display::show_hand(&self.player.hand, &selected, selector);
 
-            for i in 0..selected.len() {
-                if selected[i] {
-                    selected_cards.push(self.player.hand.cards[i].clone());
+            // Show play state of selected cards, once any are selected
+            if selected.contains(&true) {
+                let selected_play = self.selected_play(&selected);
+                display::player_note(format!("Current move: {:?}", selected_play.class), 1);
+                if selected_play.class != play::Class::Invalid {
+                    display::player_note(format!("Move Rank: {:?}", selected_play.rank), 0);
+                }
+            }
+        }
+    }
+
+    // Pick cards from the hand to give back to the player who paid tribute
+    pub fn return_tribute(&mut self, count: usize, payer_name: String) {
+        let mut selected = vec![false; self.player.hand.cards.len()];
+        let mut selector = 0usize;
+
+        display::player_note(format!("Choose {count} card(s) to give {payer_name}"), 1);
+        display::show_hand(&self.player.hand, &selected, selector);
+
+        loop {
+            match display::get_keystate() {
+                display::Input_States::Right => {
+                    selector = (selector + 1) % self.player.hand.cards.len();
+                }
+                display::Input_States::Left => {
+                    selector = ((selector as i16 - 1)
+                        .rem_euclid(self.player.hand.cards.len() as i16))
+                        as usize;
+                }
+                display::Input_States::Space => {
+                    selected[selector] = !selected[selector];
+                }
+                display::Input_States::Enter => {
+                    if selected.iter().filter(|&&is_selected| is_selected).count() == count {
+                        // Cards stay in the hand until the server accepts them
+                        let returned = self.selected_play(&selected).cards;
+                        self.client.send(Message::TributeReturn(returned));
+                        break;
+                    }
+                    self.show_rejection(play::Rejection::WrongCount);
+                }
+                _ => {
+                    continue;
                 }
             }
 
-            // Display the player's hand
             display::show_hand(&self.player.hand, &selected, selector);
+        }
+    }
+
+    // Check the revealed secret against the commitment and the hand that was played
+    fn verify_deal(&mut self, secret: &[u8], order: &[String]) {
+        let plays: Vec<&play::Play> = self.rounds[self.deal_record.first_round..]
+            .iter()
+            .flat_map(|round| round.plays.iter())
+            .collect();
+        let result = self.deal_record.verify(
+            secret,
+            &self.composition,
+            order,
+            &plays,
+            &self.player.name,
+            &self.player.hand,
+        );
+
+        // The seed is only known once the hand is over, and reproduces the deal in a bug report
+        match result {
+            Ok(seed) => self.logger.lock().unwrap().log(
+                format!("The deal was verified as fair. It was dealt with seed {seed}."),
+                Duration::ZERO,
+            ),
+            Err(e) => self
+                .display
+                .add_renderable(Arc::new(Mutex::new(Warning::new(
+                    format!("The deal could not be verified: {e}"),
+                    Duration::new(10, 0),
+                )))),
+        }
+    }
+
+    // Every player's remaining time
+    fn update_clocks(&mut self, clocks: Vec<(String, Duration)>) {
+        for (name, clock) in clocks {
+            self.player_client(&name).clock = Some(clock);
+        }
+        display::show_players(&self.players, &self.player);
+    }
 
-            // Show play state of selected cards
-            current_play = Some(play::identify_class(&mut selected_cards));
-            display::player_note(
-                format!("Current move: {:?}", current_play.as_ref().unwrap()),
-                1,
-            );
-            if current_play != Some(play::Class::Invalid) {
-                play_rank = Some(play::identify_rank(&mut selected_cards));
-                display::player_note(format!("Move Rank: {:?}", play_rank.unwrap()), 0);
+    // The entry for a player at the table, added if it is not known yet
+    fn player_client(&mut self, name: &str) -> &mut PlayerClient {
+        match self.players.iter().position(|p| p.name == name) {
+            Some(index) => &mut self.players[index],
+            None => {
+                self.players.push_back(PlayerClient::new(name));
+                self.players.back_mut().unwrap()
             }
         }
     }
 
+    fn show_rejection(&mut self, rejection: play::Rejection) {
+        self.display
+            .add_renderable(Arc::new(Mutex::new(Warning::new(
+                rejection.to_string(),
+                Duration::new(5, 0),
+            ))))
+    }
+
+    // Build a play out of the currently selected cards
+    fn selected_play(&self, selected: &[bool]) -> play::Play {
+        let mut selected_play = play::Play::new(self.player.clone());
+        selected_play.rules = self.rules;
+        selected_play.set_cards(
+            self.player
+                .hand
+                .cards
+                .iter()
+                .zip(selected)
+                .filter(|(_, &is_selected)| is_selected)
+                .map(|(card, _)| card.clone())
+                .collect(),
+        );
+        selected_play
+    }
+
     pub fn end_game(&mut self) {
         display::cleanup();
         println!("Goodbye!");
     }
 }
+
+// Plays arrive classified by the default rules, so they are classified again by the table's
+// before anything is compared with them
+fn with_table_rules(mut play: play::Play, rules: RuleSet) -> play::Play {
+    play.set_rules(rules);
+    play
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::{Card, Rank, Suit};
+
+    #[test]
+    fn plays_are_read_with_the_table_rules() {
+        let rules = RuleSet {
+            min_straight_length: 3,
+            ..RuleSet::new()
+        };
+        let mut round = Round::new();
+        let received: play::Play = "bo 3H 4H 5D".parse().unwrap();
+        assert_eq!(received.class, play::Class::Invalid);
+        round.add_play(with_table_rules(received, rules));
+        assert_eq!(
+            round.last_play().map(|play| play.class),
+            Some(play::Class::SingleStraight)
+        );
+
+        // A higher straight beats it, and is offered as a hint
+        let mut hand = Hand::new();
+        for (suit, rank) in [
+            (Suit::Hearts, Rank::Six),
+            (Suit::Hearts, Rank::Seven),
+            (Suit::Diamonds, Rank::Eight),
+        ] {
+            hand.add_card(Card::new(suit, rank));
+        }
+        let mut answer = play::Play::new(Player::new("ann"));
+        answer.rules = rules;
+        answer.set_cards(hand.cards.clone());
+        assert!(answer.resolve_against(round.last_play().unwrap()));
+        assert_eq!(play::legal_plays(&hand, round.last_play(), &rules).len(), 1);
+    }
+}
@@ -1,23 +1,42 @@
 use crate::card;
 use crate::deck;
+use crate::deck::Leftover;
 use crate::display;
+use crate::fair_deal;
+use crate::hand::Hand;
 use crate::logger::Logger;
 use crate::play;
 use crate::play::Play;
 use crate::player::Player;
+use crate::protocol::Message;
 use crate::round;
+use crate::rules::{ForfeitPolicy, MatchRules, OpeningCard, RuleSet};
 use crate::server::Server;
+use std::cmp::Reverse;
 use std::collections::VecDeque;
 use std::sync::Arc;
 use std::sync::Mutex;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+// Extra time the server waits past a deadline, so that moves sent just in time still count
+const DEADLINE_GRACE: Duration = Duration::from_secs(1);
+
+// How long clients have to answer the commitment with their entropy for the deal
+const ENTROPY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Sends to players are not checked. A send only fails once the connection is broken, and the
+// next read from that player then reports the disconnect, so moves are made for them there.
 pub struct GameServer {
     pub server: Server,
     pub players_streams: VecDeque<Player>,
     pub rounds: Vec<round::Round>,
     pub deck: deck::Deck,
     pub logger: Arc<Mutex<Logger>>,
+    pub rules: RuleSet,
+    pub finished: Vec<Player>, // Players that have gone out, in finishing order
+    pub forfeited: Vec<Player>, // Players that gave up the hand when their time ran out
+    pub match_rules: MatchRules,
+    pub opening_card: Option<card::Card>, // Card the first play of the hand must include
 }
 
 impl GameServer {
@@ -26,6 +45,8 @@ impl GameServer {
         players_streams: VecDeque<Player>,
         deck: deck::Deck,
         logger: Arc<Mutex<Logger>>,
+        rules: RuleSet,
+        match_rules: MatchRules,
     ) -> Self {
         let rounds = Vec::new();
         Self {
@@ -34,125 +55,826 @@ impl GameServer {
             rounds,
             deck,
             logger,
+            rules,
+            finished: Vec::new(),
+            forfeited: Vec::new(),
+            match_rules,
+            opening_card: None,
         }
     }
 
     pub fn start_game(&mut self) {
-        self.deal_cards();
+        if self.rules.teams {
+            self.assign_teams();
+        }
+
+        // Send the house rules to every client
+        let _ = self.server.send_all(Message::Rules(self.rules));
+
+        // Send the composition of the deck, so clients know which cards are in play
+        let composition = &self.deck.composition;
+        self.logger
+            .lock()
+            .unwrap()
+            .log(format!("Deck: {composition}"), Duration::ZERO);
+        let _ = self
+            .server
+            .send_all(Message::Composition(composition.clone()));
+
+        let match_rules = &self.match_rules;
+        self.logger
+            .lock()
+            .unwrap()
+            .log(format!("{match_rules}"), Duration::ZERO);
+
+        // Deal hands until the match is over
+        let mut hands_played = 0u16;
         loop {
-            let winner = self.play_round();
-            if !winner.is_none() {
-                let winner_name = winner.unwrap().name;
-                self.logger.lock().unwrap().log(
-                    format!("The winner is {winner_name}. Congratulations"),
-                    Duration::ZERO,
-                );
+            self.play_hand();
+            hands_played = hands_played.saturating_add(1);
+            self.score_hand();
+
+            let top_score = self
+                .players_streams
+                .iter()
+                .map(|player| player.score)
+                .max()
+                .unwrap_or(0);
+            if self.match_rules.is_over(hands_played, top_score) {
                 break;
             }
         }
+
+        // The player with the most points wins the match, along with their partner. Ties go to
+        // whoever finished higher in the last hand.
+        let last_place = |player: &Player| {
+            self.finished
+                .iter()
+                .position(|p| p == player)
+                .unwrap_or(usize::MAX)
+        };
+        let winner = self
+            .players_streams
+            .iter()
+            .max_by_key(|player| (player.score, Reverse(last_place(player))))
+            .expect("Should have at least one player");
+        let winner_names: Vec<&str> = self
+            .players_streams
+            .iter()
+            .filter(|player| *player == winner || are_teammates(player, winner))
+            .map(|player| player.name.as_str())
+            .collect();
+        let winner_name = winner_names.join(" & ");
+        let winner_score = winner.score;
+        self.logger.lock().unwrap().log(
+            format!("{winner_name} won the match with {winner_score} points."),
+            Duration::ZERO,
+        );
+        let _ = self.server.send_all(Message::GameEnd {
+            winner: winner_name,
+        });
+    }
+
+    // Seat the players so that the two teams alternate around the table
+    pub fn assign_teams(&mut self) {
+        if self.players_streams.len() != 4 {
+            self.logger.lock().unwrap().log(
+                "Teams need exactly four players, playing without them.",
+                Duration::ZERO,
+            );
+            self.rules.teams = false;
+            return;
+        }
+
+        for (seat, player) in self.players_streams.iter_mut().enumerate() {
+            player.team = Some(seat % 2);
+        }
+
+        let teams: Vec<(String, usize)> = self
+            .players_streams
+            .iter()
+            .map(|player| (player.name.clone(), player.team.unwrap()))
+            .collect();
+        let teams_str: Vec<String> = teams
+            .iter()
+            .map(|(name, team)| format!("{name}:{team}"))
+            .collect();
+        let teams_str = teams_str.join(" ");
+        self.logger
+            .lock()
+            .unwrap()
+            .log(format!("Teams: {teams_str}"), Duration::ZERO);
+        let _ = self.server.send_all(Message::Teams(teams));
     }
 
-    pub fn deal_cards(&mut self) {
-        // pick random player to start, give them a 3 of hearts as standard in the game
-        let index = (rand::random::<f32>() * self.players_streams.len() as f32).floor() as usize;
+    // Give out points by finishing place and send the scoreboard to every player
+    pub fn score_hand(&mut self) {
+        if self.rules.teams {
+            self.score_teams();
+        } else {
+            for (place, player) in self.finished.clone().iter().enumerate() {
+                let index = self.player_index(player);
+                let score = &mut self.players_streams[index].score;
+                *score = score.saturating_add(self.match_rules.points_for(place));
+            }
+        }
 
-        self.deck.sort();
+        let scores: Vec<(String, u16)> = self
+            .players_streams
+            .iter()
+            .map(|player| (player.name.clone(), player.score))
+            .collect();
+        let scores_str: Vec<String> = scores
+            .iter()
+            .map(|(name, score)| format!("{name}:{score}"))
+            .collect();
+        let scores_str = scores_str.join(" ");
+        self.logger
+            .lock()
+            .unwrap()
+            .log(format!("Scores: {scores_str}"), Duration::ZERO);
+        let _ = self.server.send_all(Message::Scores(scores));
+    }
+
+    // Partners score the points of both their places together, with a bonus for a sweep
+    fn score_teams(&mut self) {
+        let mut team_points = [0u16; 2];
+        for (place, player) in self.finished.iter().enumerate() {
+            if let Some(team) = player.team {
+                team_points[team] =
+                    team_points[team].saturating_add(self.match_rules.points_for(place));
+            }
+        }
+
+        if self.finished.len() >= 2 && are_teammates(&self.finished[0], &self.finished[1]) {
+            let first_name = &self.finished[0].name;
+            let second_name = &self.finished[1].name;
+            self.logger.lock().unwrap().log(
+                format!("{first_name} and {second_name} swept the hand."),
+                Duration::ZERO,
+            );
+            let team = self.finished[0].team.unwrap();
+            team_points[team] = team_points[team].saturating_add(self.match_rules.sweep_bonus);
+        }
+
+        for player in &mut self.players_streams {
+            if let Some(team) = player.team {
+                player.score = player.score.saturating_add(team_points[team]);
+            }
+        }
+    }
+
+    pub fn play_hand(&mut self) {
+        // The finishing order of the last hand decides who pays tribute
+        let standings = std::mem::take(&mut self.finished);
+
+        // Collect every card back before dealing, and fill up every time bank
+        for player in &mut self.players_streams {
+            player.hand = Hand::new();
+            player.clock = Duration::from_secs(self.rules.time_bank);
+        }
+        if self.rules.time_bank > 0 {
+            self.send_clocks();
+        }
+        self.deck.reset();
 
-        self.players_streams[index].hand.add_card(
-            self.deck.cards.remove(
-                self.deck
-                    .cards
-                    .binary_search(&card::Card::new(card::Suit::Hearts, card::Rank::Three))
-                    .expect("REASON"),
-            ),
+        // Commit to a secret before the clients send their entropy for the hand, so the secret
+        // cannot be picked to suit it. The seed mixes the two, and the secret is only revealed
+        // once the hand is over.
+        let secret = fair_deal::new_secret();
+        let _ = self
+            .server
+            .send_all(Message::Commitment(fair_deal::commit(&secret)));
+        let entropy = self.collect_entropy();
+        let seed_entropy: Vec<u64> = entropy.iter().map(|(_, value)| *value).collect();
+        let _ = self.server.send_all(Message::DealEntropy(entropy));
+
+        let seed = fair_deal::deal_seed(&secret, &seed_entropy);
+        self.deck.shuffle_with_seed(seed);
+        let deal_order: Vec<String> = self
+            .players_streams
+            .iter()
+            .map(|player| player.name.clone())
+            .collect();
+        self.deal_cards();
+        self.put_aside_leftovers();
+
+        if !standings.is_empty() {
+            self.pay_tribute(&standings);
+            self.send_card_counts();
+        }
+
+        // Only look for the opening card once every card has changed hands
+        let (leader, opening_card) = self.choose_leader(&standings);
+        self.rotate_to(&leader);
+        let leader_name = &leader.name;
+        let opening = match &opening_card {
+            Some(card) => format!(" with the {card}"),
+            None => String::new(),
+        };
+        self.logger.lock().unwrap().log(
+            format!("{leader_name} will start first{opening}."),
+            Duration::ZERO,
         );
+        let _ = self.server.send_all(Message::Leader {
+            name: leader_name.clone(),
+            card: opening_card.clone(),
+        });
+        self.opening_card = opening_card.filter(|_| self.rules.must_play_opening_card);
+
+        // Keep playing until only one player is left holding cards
+        while self.active_players() > 1 {
+            self.play_round();
+        }
+
+        // The last player left takes last place
+        let last_player = self
+            .players_streams
+            .iter()
+            .find(|player| !self.is_out(player))
+            .cloned();
+        if let Some(last_player) = last_player {
+            self.finished.push(last_player);
+        }
+
+        // Players who forfeited come after them, the first to forfeit placing last
+        let forfeited = std::mem::take(&mut self.forfeited);
+        self.finished.extend(forfeited.into_iter().rev());
 
-        let first_player_name = &self.players_streams[index].name;
+        // Reveal the secret so that every client can check the deal. Only now that nobody can
+        // use it to see other hands is the seed shown.
         self.logger.lock().unwrap().log(
-            format!("{first_player_name} will start first."),
+            format!("The hand was dealt with seed {seed}."),
             Duration::ZERO,
         );
+        let _ = self.server.send_all(Message::Reveal {
+            secret: secret.to_vec(),
+            order: deal_order,
+        });
 
-        // Ensure first player is last when distributing cards to balance hand size
-        for _ in index..self.players_streams.len() - 1 {
+        let winner_name = &self.finished[0].name;
+        self.logger.lock().unwrap().log(
+            format!("{winner_name} won the hand. Congratulations"),
+            Duration::ZERO,
+        );
+
+        // send the final standings to all players, from first to last place
+        let standings: Vec<String> = self
+            .finished
+            .iter()
+            .map(|player| player.name.clone())
+            .collect();
+        let _ = self.server.send_all(Message::Standings(standings));
+    }
+
+    // Fresh entropy for the deal from every player, in seating order. Players who do not
+    // answer the commitment in time are left out.
+    fn collect_entropy(&mut self) -> Vec<(String, u64)> {
+        let deadline = Instant::now() + ENTROPY_TIMEOUT;
+        let mut entropy = Vec::new();
+        for player in self.players_streams.clone() {
+            loop {
+                match self.server.read_before(&player, deadline) {
+                    Ok(Some(Message::Entropy(value))) => {
+                        entropy.push((player.name.clone(), value));
+                        break;
+                    }
+                    // Anything else answers an earlier prompt
+                    Ok(Some(_)) => continue,
+                    Ok(None) | Err(_) => {
+                        let player_name = &player.name;
+                        self.logger.lock().unwrap().log(
+                            format!("{player_name} sent no entropy for the deal."),
+                            Duration::ZERO,
+                        );
+                        break;
+                    }
+                }
+            }
+        }
+        entropy
+    }
+
+    // Number of players that still hold cards
+    pub fn active_players(&self) -> usize {
+        self.players_streams
+            .iter()
+            .filter(|player| !self.is_out(player))
+            .count()
+    }
+
+    // Whether a player has gone out or forfeited the hand
+    pub fn is_out(&self, player: &Player) -> bool {
+        self.finished.contains(player) || self.forfeited.contains(player)
+    }
+
+    // Send every player's remaining time
+    pub fn send_clocks(&mut self) {
+        let clocks: Vec<(String, Duration)> = self
+            .players_streams
+            .iter()
+            .map(|player| (player.name.clone(), player.clock))
+            .collect();
+        let _ = self.server.send_all(Message::Clocks(clocks));
+    }
+
+    pub fn deal_cards(&mut self) {
+        // Deal the shuffled deck one card at a time around the table
+        let hands = self.deck.deal(self.players_streams.len());
+        for (player, cards) in self.players_streams.iter_mut().zip(hands) {
+            player.hand.cards = cards;
+            player.hand.sort();
+        }
+
+        // Send every player their own hand, along with the seating order and card counts
+        let seats = self.card_counts();
+        for player in self.players_streams.clone() {
+            let deal = Message::Deal {
+                seats: seats.clone(),
+                hand: player.hand.cards.clone(),
+            };
+            let _ = self.server.send(deal, &player);
+        }
+    }
+
+    // Every player's name and number of cards, in seating order
+    fn card_counts(&self) -> Vec<(String, usize)> {
+        self.players_streams
+            .iter()
+            .map(|player| (player.name.clone(), player.hand.cards.len()))
+            .collect()
+    }
+
+    pub fn send_card_counts(&mut self) {
+        let counts = self.card_counts();
+        let _ = self.server.send_all(Message::CardCounts(counts));
+    }
+
+    // Cards that could not be dealt evenly are put aside, face up if the host chose so
+    fn put_aside_leftovers(&mut self) {
+        let leftover = std::mem::take(&mut self.deck.cards);
+        if leftover.is_empty() {
+            return;
+        }
+
+        let count = leftover.len();
+        let cards = match self.deck.composition.leftover {
+            Leftover::SetAside => Vec::new(),
+            Leftover::Shown => leftover,
+        };
+        let cards_str: Vec<String> = cards.iter().map(|card| card.to_string()).collect();
+        let cards_str = cards_str.join(" ");
+        self.logger.lock().unwrap().log(
+            format!("{count} leftover card(s) put aside. {cards_str}"),
+            Duration::ZERO,
+        );
+        let _ = self.server.send_all(Message::Leftover { count, cards });
+    }
+
+    // Find who leads the first round of a hand, and the card they have to open with if any
+    fn choose_leader(&self, standings: &[Player]) -> (Player, Option<card::Card>) {
+        // In match play the winner of the last hand can lead
+        if self.rules.winner_leads {
+            if let Some(winner) = standings.first() {
+                let index = self.player_index(winner);
+                return (self.players_streams[index].clone(), None);
+            }
+        }
+
+        let holds = |card: &card::Card| {
+            self.players_streams
+                .iter()
+                .find(|player| player.hand.cards.contains(card))
+        };
+        if self.rules.opening_card == OpeningCard::ThreeOfHearts {
+            let three_of_hearts = card::Card::new(card::Suit::Hearts, card::Rank::Three);
+            if let Some(player) = holds(&three_of_hearts) {
+                return (player.clone(), Some(three_of_hearts));
+            }
+        }
+
+        // Otherwise whoever holds the lowest card leads, or the first player if nothing was dealt
+        let lowest = self
+            .players_streams
+            .iter()
+            .flat_map(|player| player.hand.cards.iter())
+            .min_by(|a, b| a.cmp_suit_tie_break(b).then(a.deck.cmp(&b.deck)))
+            .cloned();
+        match lowest.as_ref().and_then(holds) {
+            Some(player) => (player.clone(), lowest),
+            None => (self.players_streams[0].clone(), None),
+        }
+    }
+
+    // Rotate the table until the player is at the front
+    fn rotate_to(&mut self, player: &Player) {
+        while self.players_streams.front().unwrap() != player {
             let last_player = self
                 .players_streams
                 .pop_front()
                 .expect("Should have at least one player");
 
-            self.players_streams.insert(0, last_player);
+            self.players_streams.push_back(last_player);
+        }
+    }
+
+    // The last player gives their best cards to the winner, who gives back cards of their
+    // choice. With a tribute of 2 cards, the second to last player also gives 1 card to the
+    // player in second place. A player holding both jokers does not pay tribute.
+    pub fn pay_tribute(&mut self, standings: &[Player]) {
+        let num_players = standings.len();
+        if num_players < 2 {
+            return;
         }
 
-        // deal the rest of the cards
-        while !self.deck.is_empty() {
-            for player in &mut self.players_streams {
-                if self.deck.is_empty() {
-                    break;
+        // (payer place, receiver place, number of cards)
+        let mut tributes = Vec::new();
+        match self.rules.tribute {
+            0 => {}
+            1 => tributes.push((num_players - 1, 0, 1)),
+            _ => {
+                tributes.push((num_players - 1, 0, 2));
+                if num_players >= 4 {
+                    tributes.push((num_players - 2, 1, 1));
                 }
-                player.hand.add_card(self.deck.draw_card());
-                player.hand.sort();
             }
         }
 
-        //After dealing, put first_player in the first position
-        let last_player = self
-            .players_streams
-            .pop_front()
-            .expect("Should have at least one player");
+        for (payer_place, receiver_place, count) in tributes {
+            let payer = &standings[payer_place];
+            let receiver = &standings[receiver_place];
+            let payer_index = self.player_index(payer);
+            let receiver_index = self.player_index(receiver);
+            let payer_name = &payer.name;
+            let receiver_name = &receiver.name;
 
-        self.players_streams.insert(0, last_player);
+            if self.players_streams[payer_index].hand.has_both_jokers() {
+                self.logger.lock().unwrap().log(
+                    format!("{payer_name} holds both jokers and does not pay tribute."),
+                    Duration::ZERO,
+                );
+                let _ = self.server.send_all(Message::Tribute {
+                    from: payer_name.clone(),
+                    to: receiver_name.clone(),
+                    cards: Vec::new(),
+                });
+                continue;
+            }
+
+            let cards = self.players_streams[payer_index].hand.best_cards(count);
+            self.give_cards(payer_index, receiver_index, &cards);
+
+            // Prompt the receiver until they choose cards to give back. The deadline covers
+            // every attempt, like a turn.
+            let deadline = (self.rules.turn_time > 0)
+                .then(|| Instant::now() + Duration::from_secs(self.rules.turn_time));
+            let returned = loop {
+                let _ = self.server.send(
+                    Message::AskTribute {
+                        count,
+                        to: payer_name.clone(),
+                    },
+                    receiver,
+                );
 
-        //TODO: Send players their cards, also send order of players
+                let response = match deadline {
+                    Some(deadline) => self.server.read_before(receiver, deadline + DEADLINE_GRACE),
+                    None => self.server.read(receiver).map(Some),
+                };
+                let returned = match response {
+                    Ok(Some(Message::TributeReturn(returned))) => returned,
+                    Ok(Some(_)) => {
+                        self.logger.lock().unwrap().log(
+                            format!("Could not read cards from {receiver_name}"),
+                            Duration::ZERO,
+                        );
+                        let _ = self
+                            .server
+                            .send(Message::Reject(play::Rejection::Malformed), receiver);
+                        continue;
+                    }
+                    // A receiver who runs out of time or is gone gives back their lowest cards
+                    Ok(None) => {
+                        self.logger
+                            .lock()
+                            .unwrap()
+                            .log(format!("{receiver_name} ran out of time."), Duration::ZERO);
+                        break self.players_streams[receiver_index]
+                            .hand
+                            .lowest_cards(count);
+                    }
+                    Err(e) if e.is_disconnect() => {
+                        self.logger
+                            .lock()
+                            .unwrap()
+                            .log(format!("{receiver_name} disconnected: {e}"), Duration::ZERO);
+                        break self.players_streams[receiver_index]
+                            .hand
+                            .lowest_cards(count);
+                    }
+                    Err(e) => {
+                        self.logger.lock().unwrap().log(
+                            format!("Could not read cards from {receiver_name}: {e}"),
+                            Duration::ZERO,
+                        );
+                        let _ = self
+                            .server
+                            .send(Message::Reject(play::Rejection::Malformed), receiver);
+                        continue;
+                    }
+                };
+
+                let rejection = if returned.len() != count {
+                    play::Rejection::WrongCount
+                } else if !self.players_streams[receiver_index]
+                    .hand
+                    .contains_cards(&returned)
+                {
+                    play::Rejection::NotOwned
+                } else {
+                    break returned;
+                };
+                let _ = self.server.send(Message::Reject(rejection), receiver);
+            };
+
+            self.give_cards(receiver_index, payer_index, &returned);
+        }
     }
 
-    pub fn play_round(&mut self) -> Option<Player> {
-        //Optionally return a winner
+    // Move cards from one hand to another and tell every player about it
+    fn give_cards(&mut self, from_index: usize, to_index: usize, cards: &[card::Card]) {
+        self.players_streams[from_index].hand.remove_cards(cards);
+        for card in cards {
+            self.players_streams[to_index].hand.add_card(card.clone());
+        }
+        self.players_streams[to_index].hand.sort();
+
+        let from_name = &self.players_streams[from_index].name;
+        let to_name = &self.players_streams[to_index].name;
+        let cards_str: Vec<String> = cards.iter().map(|card| card.to_string()).collect();
+        let cards_str = cards_str.join(" ");
+        self.logger.lock().unwrap().log(
+            format!("{from_name} gave {cards_str} to {to_name}."),
+            Duration::ZERO,
+        );
+        let tribute = Message::Tribute {
+            from: from_name.clone(),
+            to: to_name.clone(),
+            cards: cards.to_vec(),
+        };
+        let _ = self.server.send_all(tribute);
+    }
+
+    fn player_index(&self, player: &Player) -> usize {
+        self.players_streams
+            .iter()
+            .position(|p| p == player)
+            .expect("Player should be in the game")
+    }
+
+    pub fn play_round(&mut self) {
         let mut round = round::Round::new(); //Initialize new round
-        self.server.send_all("r\0"); //Send new round to clients
+        let _ = self.server.send_all(Message::NewRound); //Send new round to clients
 
         'round: loop {
             // loop until everybody skips
-            for player in &self.players_streams {
-                // If everyone besides the last play has skipped their turn, end round
-                if !round.plays.is_empty() && &*player == &round.plays.last().unwrap().player {
+            for index in 0..self.players_streams.len() {
+                let player = self.players_streams[index].clone();
+
+                // If everyone besides the last play has passed their turn, end round.
+                // A player who has gone out still counts as the author of the last play.
+                let last_out = round.winner().is_some_and(|winner| self.is_out(winner));
+                if round.is_over(self.active_players() + last_out as usize) {
                     break 'round;
                 }
 
-                // TODO: Add non panicking error handling here
+                // Players who have gone out are skipped
+                if self.is_out(&player) {
+                    continue;
+                }
+
+                // With the partner rule, nobody has to answer their own partner's play
+                if self.rules.partner_ends_obligation
+                    && round
+                        .winner()
+                        .is_some_and(|winner| are_teammates(winner, &player))
+                {
+                    let pass = Play::pass(player.clone());
+                    round.plays.push(pass.clone());
+                    let _ = self.server.send_all(pass);
+                    continue;
+                }
+
+                // The deadline covers the whole turn, including rejected plays. With a time
+                // bank it is however much time the player has left.
+                let turn_start = Instant::now();
+                let deadline = if self.rules.time_bank > 0 {
+                    Some(turn_start + player.clock)
+                } else if self.rules.turn_time > 0 {
+                    Some(turn_start + Duration::from_secs(self.rules.turn_time))
+                } else {
+                    None
+                };
+                let mut timed_out = false;
+
+                // Prompt the player until they send a play the server accepts
+                let play = loop {
+                    // Once a player's flag has fallen every move is made for them
+                    if self.rules.time_bank > 0 && player.clock.is_zero() {
+                        break self.timeout_play(&player, round.last_play().is_none());
+                    }
+
+                    // Anything sent before this prompt answers an earlier one, e.g. a move
+                    // that arrived after its deadline
+                    if let Ok(discarded @ 1..) = self.server.discard_pending(&player) {
+                        let player_name = &player.name;
+                        self.logger.lock().unwrap().log(
+                            format!("Ignored {discarded} late message(s) from {player_name}."),
+                            Duration::ZERO,
+                        );
+                    }
 
-                // sends move command to player
-                self.server.send("m\0", player);
+                    // sends move command to player, along with the time left to move
+                    let time_left =
+                        deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+                    let _ = self.server.send(Message::Prompt { time_left }, &player);
 
-                // read response from player
-                let play_str = self
-                    .server
-                    .read(player)
-                    .expect("Invalid play sent by player");
+                    // read response from player. Moves are made for a player who disconnects,
+                    // and anything unreadable is rejected so the player can try again.
+                    let response = match deadline {
+                        Some(deadline) => {
+                            self.server.read_before(&player, deadline + DEADLINE_GRACE)
+                        }
+                        None => self.server.read(&player).map(Some),
+                    };
+                    let response = match response {
+                        Ok(response) => response,
+                        Err(e) if e.is_disconnect() => {
+                            let player_name = &player.name;
+                            self.logger
+                                .lock()
+                                .unwrap()
+                                .log(format!("{player_name} disconnected: {e}"), Duration::ZERO);
+                            break self.timeout_play(&player, round.last_play().is_none());
+                        }
+                        Err(e) => {
+                            let player_name = &player.name;
+                            self.logger.lock().unwrap().log(
+                                format!("Could not read play from {player_name}: {e}"),
+                                Duration::ZERO,
+                            );
+                            let _ = self
+                                .server
+                                .send(Message::Reject(play::Rejection::Malformed), &player);
+                            continue;
+                        }
+                    };
+                    let Some(response) = response else {
+                        let player_name = &player.name;
+                        self.logger
+                            .lock()
+                            .unwrap()
+                            .log(format!("{player_name} ran out of time."), Duration::ZERO);
+                        timed_out = true;
+                        break self.timeout_play(&player, round.last_play().is_none());
+                    };
 
-                let play: Play = play_str.clone().into();
+                    let mut play = match response {
+                        Message::Play(play) => play,
+                        Message::Pass(_) => Play::pass(player.clone()),
+                        _ => {
+                            let player_name = &player.name;
+                            self.logger.lock().unwrap().log(
+                                format!("Could not read play from {player_name}"),
+                                Duration::ZERO,
+                            );
+                            let _ = self
+                                .server
+                                .send(Message::Reject(play::Rejection::Malformed), &player);
+                            continue;
+                        }
+                    };
+                    // Plays are always attributed to the player that was prompted
+                    play.player = player.clone();
+                    // Cards the player does not hold are refused before resolving any jokers
+                    if !player.hand.contains_cards(&play.cards) {
+                        let player_name = &player.name;
+                        self.logger.lock().unwrap().log(
+                            format!(
+                                "Rejected play from {player_name}: {}",
+                                play::Rejection::NotOwned
+                            ),
+                            Duration::ZERO,
+                        );
+                        let _ = self
+                            .server
+                            .send(Message::Reject(play::Rejection::NotOwned), &player);
+                        continue;
+                    }
+                    play.set_rules(self.rules);
 
-                round.plays.push(play);
+                    // The first play of the hand may have to include the opening card
+                    let validation =
+                        play.validate(&player.hand, round.last_play())
+                            .and_then(|()| match &self.opening_card {
+                                Some(card) if !play.cards.contains(card) => {
+                                    Err(play::Rejection::MissingOpeningCard)
+                                }
+                                _ => Ok(()),
+                            });
+                    match validation {
+                        Ok(()) => break play,
+                        Err(rejection) => {
+                            let player_name = &player.name;
+                            self.logger.lock().unwrap().log(
+                                format!("Rejected play from {player_name}: {rejection}"),
+                                Duration::ZERO,
+                            );
+                            let _ = self.server.send(Message::Reject(rejection), &player);
+                        }
+                    }
+                };
 
-                // win condition, end rounds and announce win
-                if player.hand.cards.is_empty() {
-                    let winner_name = &player.name;
-                    self.server.send_all(format!("w{winner_name}"));
-                    return Some(player.clone());
+                // Only the first turn of the hand has to include the opening card, even if the
+                // player forfeits it
+                self.opening_card = None;
+
+                if self.rules.time_bank > 0 {
+                    // The flag falls once the clock runs down, even if a move arrived during
+                    // the grace period
+                    let clock = &mut self.players_streams[index].clock;
+                    let remaining = clock.saturating_sub(turn_start.elapsed());
+                    let flag_fell = !clock.is_zero() && (timed_out || remaining.is_zero());
+                    if remaining.is_zero() || timed_out {
+                        *clock = Duration::ZERO;
+                    } else {
+                        *clock = remaining + Duration::from_secs(self.rules.increment);
+                    }
+
+                    if flag_fell && self.rules.forfeit == ForfeitPolicy::Forfeit {
+                        // The player gives up the hand, and their cards leave the game
+                        self.players_streams[index].hand = Hand::new();
+                        self.forfeited.push(player.clone());
+                        let player_name = &player.name;
+                        self.logger.lock().unwrap().log(
+                            format!("{player_name} ran out of time and forfeits the hand."),
+                            Duration::ZERO,
+                        );
+                        let _ = self.server.send_all(Message::Forfeit(player_name.clone()));
+                        self.send_clocks();
+                        self.send_card_counts();
+
+                        if self.active_players() <= 1 {
+                            break 'round;
+                        }
+                        continue;
+                    }
+                    self.send_clocks();
                 }
 
-                // send the players the new play
-                self.server.send_all(format!("p{play_str}\0"));
+                self.players_streams[index].hand.remove_cards(&play.cards);
+                round.plays.push(play.clone());
+
+                // send the players the new play, and how many cards everyone has left
+                let _ = self.server.send_all(play);
+                self.send_card_counts();
+
+                // The player goes out once their hand is empty, and takes the next place
+                if self.players_streams[index].hand.cards.is_empty() {
+                    self.finished.push(self.players_streams[index].clone());
+                    let place = self.finished.len();
+                    let player_name = &player.name;
+                    self.logger.lock().unwrap().log(
+                        format!("{player_name} finished in place {place}."),
+                        Duration::ZERO,
+                    );
+                    let _ = self.server.send_all(Message::WentOut {
+                        place,
+                        name: player_name.clone(),
+                    });
+
+                    // The hand is over once only one player holds cards
+                    if self.active_players() <= 1 {
+                        break 'round;
+                    }
+                }
             }
         }
 
-        let winner = &round.plays.last().unwrap().player;
-        let winner_name = &winner.name;
-        self.logger.lock().unwrap().log(
-            format!("{winner_name} won the round. They will start the next round."),
-            Duration::ZERO,
-        );
+        // The round can end without any play if the leader forfeits
+        if let Some(winner) = round.winner().cloned() {
+            let winner_name = &winner.name;
+            self.logger
+                .lock()
+                .unwrap()
+                .log(format!("{winner_name} won the round."), Duration::ZERO);
 
-        while *self.players_streams.front().unwrap() != round.plays.last().unwrap().player {
+            // The winner of the round leads the next one
+            self.rotate_to(&winner);
+        }
+
+        // If the winner has already gone out, the lead passes to the next player with cards
+        while self.active_players() > 0 && self.is_out(self.players_streams.front().unwrap()) {
             let last_player = self
                 .players_streams
                 .pop_front()
@@ -162,9 +884,29 @@ impl GameServer {
         }
 
         // send to all players that the round has ended
-        self.server.send_all(format!("e\0"));
+        let _ = self.server.send_all(Message::RoundEnd);
         self.rounds.push(round);
-        None
+    }
+
+    // The move made for a player who runs out of time: a pass, or their lowest single
+    // when leading the round
+    fn timeout_play(&self, player: &Player, leading: bool) -> Play {
+        if !leading {
+            return Play::pass(player.clone());
+        }
+        let lowest = match &self.opening_card {
+            Some(card) if player.hand.cards.contains(card) => Some(card.clone()),
+            _ => player
+                .hand
+                .cards
+                .iter()
+                .min_by(|a, b| a.cmp_suit_tie_break(b))
+                .cloned(),
+        };
+        let mut play = Play::new(player.clone());
+        play.rules = self.rules;
+        play.set_cards(lowest.into_iter().collect());
+        play
     }
 
     pub fn end_game(&mut self) {
@@ -172,3 +914,489 @@ impl GameServer {
         println!("Goodbye!");
     }
 }
+
+// Whether two different players are partners on the same team
+pub fn are_teammates(a: &Player, b: &Player) -> bool {
+    a != b && a.team.is_some() && a.team == b.team
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::{Card, Rank, Suit};
+    use crate::protocol::{self, Codec};
+    use crate::server::Connection;
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    // A game between players connected over localhost, with the client end of every connection
+    fn table(names: &[&str], rules: RuleSet) -> (GameServer, Vec<TcpStream>) {
+        let logger = Arc::new(Mutex::new(Logger::new()));
+        let server = Server::new(logger.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut players = VecDeque::new();
+        let mut clients = Vec::new();
+        for name in names {
+            let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            client
+                .set_read_timeout(Some(Duration::from_secs(10)))
+                .unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            let player = Player::new(*name);
+            server.player_network.lock().unwrap().push_back((
+                player.clone(),
+                Connection::new(stream, Codec::Native),
+                Instant::now(),
+            ));
+            players.push_back(player);
+            clients.push(client);
+        }
+        let game = GameServer::new(
+            server,
+            players,
+            deck::Deck::new(1),
+            logger,
+            rules,
+            MatchRules::new(),
+        );
+        (game, clients)
+    }
+
+    fn give_hand(game: &mut GameServer, index: usize, cards: &[Card]) {
+        game.players_streams[index].hand = Hand {
+            cards: cards.to_vec(),
+        };
+    }
+
+    // A client that answers each prompt with the next move, e.g. "ann 3H" or "pass", until
+    // the given number of rounds has ended. Returns every message it received.
+    fn answer_prompts(
+        mut client: TcpStream,
+        moves: &[&str],
+        rounds: usize,
+    ) -> thread::JoinHandle<Vec<Message>> {
+        let mut moves: VecDeque<String> = moves.iter().map(|m| m.to_string()).collect();
+        thread::spawn(move || {
+            let mut received = Vec::new();
+            let mut rounds_left = rounds;
+            while rounds_left > 0 {
+                let message = protocol::read_message(&mut client).unwrap();
+                match &message {
+                    Message::Prompt { .. } => {
+                        let next = moves.pop_front().expect("Should have a move left");
+                        let answer = match next.as_str() {
+                            "pass" => Message::Pass(String::new()),
+                            play => Message::Play(play.parse().unwrap()),
+                        };
+                        protocol::write_message(&mut client, &answer).unwrap();
+                    }
+                    Message::RoundEnd => rounds_left -= 1,
+                    _ => {}
+                }
+                received.push(message);
+            }
+            received
+        })
+    }
+
+    #[test]
+    fn last_place_pays_tribute_to_the_winner() {
+        let (mut game, mut clients) = table(&["ann", "bo"], RuleSet::new());
+        let four = Card::new(Suit::Hearts, Rank::Four);
+        let joker = Card::new(Suit::Red, Rank::Joker);
+        give_hand(
+            &mut game,
+            0,
+            &[four.clone(), Card::new(Suit::Hearts, Rank::Five)],
+        );
+        give_hand(
+            &mut game,
+            1,
+            &[Card::new(Suit::Hearts, Rank::Three), joker.clone()],
+        );
+
+        // The winner first gives back the wrong number of cards, then one card
+        let mut ann = clients.remove(0);
+        let returned = four.clone();
+        let winner = thread::spawn(move || {
+            let mut answers = vec![vec![returned], Vec::new()];
+            while !answers.is_empty() {
+                match protocol::read_message(&mut ann).unwrap() {
+                    Message::AskTribute { count, to } => {
+                        assert_eq!((count, to.as_str()), (1, "bo"));
+                        let cards = answers.pop().unwrap();
+                        protocol::write_message(&mut ann, &Message::TributeReturn(cards)).unwrap();
+                    }
+                    Message::Reject(rejection) => {
+                        assert_eq!(rejection, play::Rejection::WrongCount)
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        let standings: Vec<Player> = game.players_streams.iter().cloned().collect();
+        game.pay_tribute(&standings);
+        winner.join().unwrap();
+
+        assert!(game.players_streams[0].hand.contains_cards(&[joker]));
+        assert!(!game.players_streams[0].hand.cards.contains(&four));
+        assert!(game.players_streams[1].hand.contains_cards(&[four]));
+    }
+
+    #[test]
+    fn silent_winner_returns_their_lowest_cards() {
+        let rules = RuleSet {
+            turn_time: 1,
+            ..RuleSet::new()
+        };
+        let (mut game, _clients) = table(&["ann", "bo"], rules);
+        let four = Card::new(Suit::Hearts, Rank::Four);
+        let joker = Card::new(Suit::Red, Rank::Joker);
+        give_hand(
+            &mut game,
+            0,
+            &[four.clone(), Card::new(Suit::Hearts, Rank::Five)],
+        );
+        give_hand(
+            &mut game,
+            1,
+            &[Card::new(Suit::Hearts, Rank::Three), joker.clone()],
+        );
+
+        // ann never answers, so the deadline gives back their lowest card
+        let standings: Vec<Player> = game.players_streams.iter().cloned().collect();
+        let start = Instant::now();
+        game.pay_tribute(&standings);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        assert!(game.players_streams[0].hand.contains_cards(&[joker]));
+        assert!(game.players_streams[1].hand.contains_cards(&[four]));
+    }
+
+    #[test]
+    fn garbled_moves_are_rejected_and_disconnects_pass() {
+        let (mut game, mut clients) = table(&["ann", "bo"], RuleSet::new());
+        let three = Card::new(Suit::Hearts, Rank::Three);
+        let four = Card::new(Suit::Hearts, Rank::Four);
+        give_hand(&mut game, 0, &[three, four.clone()]);
+        give_hand(&mut game, 1, &[Card::new(Suit::Spades, Rank::Six)]);
+
+        // ann first sends a play that cannot be read, and bo has left the table
+        drop(clients.remove(1));
+        let mut ann = clients.remove(0);
+        let leader = thread::spawn(move || {
+            let mut prompts = 0;
+            loop {
+                match protocol::read_message(&mut ann).unwrap() {
+                    Message::Prompt { .. } => {
+                        prompts += 1;
+                        if prompts == 1 {
+                            let garbled = b"pann ZZ";
+                            ann.write_all(&(garbled.len() as u32).to_be_bytes())
+                                .unwrap();
+                            ann.write_all(garbled).unwrap();
+                        } else {
+                            let play = Message::Play("ann 3H".parse().unwrap());
+                            protocol::write_message(&mut ann, &play).unwrap();
+                        }
+                    }
+                    Message::Reject(rejection) => {
+                        assert_eq!(rejection, play::Rejection::Malformed)
+                    }
+                    Message::RoundEnd => return prompts,
+                    _ => {}
+                }
+            }
+        });
+
+        game.play_round();
+        assert_eq!(leader.join().unwrap(), 2);
+        assert!(game.players_streams[0].hand.cards == vec![four]);
+        assert!(game.rounds[0]
+            .winner()
+            .is_some_and(|winner| winner.name == "ann"));
+    }
+
+    #[test]
+    fn lead_passes_on_when_the_leader_goes_out() {
+        let (mut game, clients) = table(&["ann", "bo", "cy"], RuleSet::new());
+        give_hand(&mut game, 0, &[Card::new(Suit::Hearts, Rank::Three)]);
+        give_hand(&mut game, 1, &[Card::new(Suit::Spades, Rank::Five)]);
+        give_hand(
+            &mut game,
+            2,
+            &[
+                Card::new(Suit::Spades, Rank::Four),
+                Card::new(Suit::Spades, Rank::Seven),
+            ],
+        );
+
+        // ann goes out on the lead and nobody answers, then bo leads and goes out too
+        let mut clients = clients.into_iter();
+        let ann = answer_prompts(clients.next().unwrap(), &["ann 3H"], 2);
+        let bo = answer_prompts(clients.next().unwrap(), &["pass", "bo 5S"], 2);
+        let cy = answer_prompts(clients.next().unwrap(), &["pass"], 2);
+
+        game.play_round();
+        assert_eq!(game.players_streams[0].name, "bo");
+        game.play_round();
+        assert_eq!(game.active_players(), 1);
+
+        let places: Vec<&str> = game
+            .finished
+            .iter()
+            .map(|player| player.name.as_str())
+            .collect();
+        assert_eq!(places, ["ann", "bo"]);
+        let cy_messages = cy.join().unwrap();
+        let went_out: Vec<(usize, &str)> = cy_messages
+            .iter()
+            .filter_map(|message| match message {
+                Message::WentOut { place, name } => Some((*place, name.as_str())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(went_out, [(1, "ann"), (2, "bo")]);
+        ann.join().unwrap();
+        bo.join().unwrap();
+    }
+
+    #[test]
+    fn time_bank_is_charged_for_each_move() {
+        let rules = RuleSet {
+            time_bank: 10,
+            increment: 2,
+            ..RuleSet::new()
+        };
+        let (mut game, clients) = table(&["ann", "bo"], rules);
+        give_hand(
+            &mut game,
+            0,
+            &[
+                Card::new(Suit::Hearts, Rank::Three),
+                Card::new(Suit::Hearts, Rank::Nine),
+            ],
+        );
+        give_hand(&mut game, 1, &[Card::new(Suit::Spades, Rank::Four)]);
+        for player in &mut game.players_streams {
+            player.clock = Duration::from_secs(10);
+        }
+
+        // ann thinks for a moment before leading, and bo passes straight away
+        let mut clients = clients.into_iter();
+        let mut ann = clients.next().unwrap();
+        let leader = thread::spawn(move || loop {
+            match protocol::read_message(&mut ann).unwrap() {
+                Message::Prompt { time_left } => {
+                    assert!(time_left.is_some_and(|time_left| time_left <= Duration::from_secs(10)));
+                    thread::sleep(Duration::from_millis(300));
+                    let play = Message::Play("ann 3H".parse().unwrap());
+                    protocol::write_message(&mut ann, &play).unwrap();
+                }
+                Message::RoundEnd => return,
+                _ => {}
+            }
+        });
+        let bo = answer_prompts(clients.next().unwrap(), &["pass"], 1);
+
+        game.play_round();
+        leader.join().unwrap();
+        let ann_clock = game.players_streams[0].clock;
+        assert!(ann_clock > Duration::from_secs(11) && ann_clock <= Duration::from_millis(11_700));
+        let bo_clock = game.players_streams[1].clock;
+        assert!(bo_clock > Duration::from_millis(11_700) && bo_clock <= Duration::from_secs(12));
+
+        // Everyone hears the clocks after each move
+        let clocks = bo
+            .join()
+            .unwrap()
+            .into_iter()
+            .filter_map(|message| match message {
+                Message::Clocks(clocks) => Some(clocks),
+                _ => None,
+            });
+        assert_eq!(clocks.count(), 2);
+    }
+
+    #[test]
+    fn fallen_flags_lead_the_lowest_single_and_pass_when_following() {
+        let rules = RuleSet {
+            time_bank: 10,
+            ..RuleSet::new()
+        };
+        let (mut game, _clients) = table(&["ann", "bo"], rules);
+        let four = Card::new(Suit::Hearts, Rank::Four);
+        let nine = Card::new(Suit::Spades, Rank::Nine);
+        let five = Card::new(Suit::Spades, Rank::Five);
+        give_hand(&mut game, 0, &[nine.clone(), four.clone()]);
+        give_hand(&mut game, 1, std::slice::from_ref(&five));
+
+        // Both clocks have run out, so neither player is prompted
+        game.play_round();
+        let round = &game.rounds[0];
+        assert!(round.plays[0].cards == vec![four]);
+        assert_eq!(round.plays[1].class, play::Class::Pass);
+        assert!(round.winner().is_some_and(|winner| winner.name == "ann"));
+        assert!(game.players_streams[0].hand.cards == vec![nine]);
+        assert!(game.players_streams[1].hand.cards == vec![five]);
+    }
+
+    #[test]
+    fn players_see_their_hand_and_the_card_counts() {
+        let (mut game, clients) = table(&["ann", "bo"], RuleSet::new());
+        game.deal_cards();
+        let hands: Vec<Vec<Card>> = game
+            .players_streams
+            .iter()
+            .map(|player| player.hand.cards.clone())
+            .collect();
+
+        // ann goes out on the lead, which ends the hand
+        give_hand(&mut game, 0, &[Card::new(Suit::Hearts, Rank::Three)]);
+        give_hand(
+            &mut game,
+            1,
+            &[
+                Card::new(Suit::Spades, Rank::Five),
+                Card::new(Suit::Spades, Rank::Six),
+            ],
+        );
+        let mut clients = clients.into_iter();
+        let ann = answer_prompts(clients.next().unwrap(), &["ann 3H"], 1);
+        let bo = answer_prompts(clients.next().unwrap(), &[], 1);
+        game.play_round();
+
+        for (client, hand) in [ann, bo].into_iter().zip(hands) {
+            let received = client.join().unwrap();
+            let Some(Message::Deal { seats, hand: dealt }) = received.first() else {
+                panic!("Should be dealt a hand first");
+            };
+            assert!(*dealt == hand);
+            assert_eq!(*seats, [("ann".to_string(), 27), ("bo".to_string(), 27)]);
+
+            let counts: Vec<&Vec<(String, usize)>> = received
+                .iter()
+                .filter_map(|message| match message {
+                    Message::CardCounts(counts) => Some(counts),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(
+                counts,
+                [&vec![("ann".to_string(), 0), ("bo".to_string(), 2)]]
+            );
+        }
+    }
+
+    #[test]
+    fn places_add_up_over_the_match() {
+        let (mut game, mut clients) = table(&["ann", "bo", "cy"], RuleSet::new());
+        let finish = |game: &mut GameServer, order: [usize; 3]| {
+            game.finished = order
+                .iter()
+                .map(|index| game.players_streams[*index].clone())
+                .collect();
+            game.score_hand();
+        };
+
+        // 3, 2 and 1 points from first place down
+        finish(&mut game, [1, 2, 0]);
+        finish(&mut game, [0, 1, 2]);
+        let scores: Vec<u16> = game
+            .players_streams
+            .iter()
+            .map(|player| player.score)
+            .collect();
+        assert_eq!(scores, [4, 5, 3]);
+        assert!(!game.match_rules.is_over(2, 5));
+        assert!(game.match_rules.is_over(4, 10));
+
+        // Every player is sent the scoreboard after each hand
+        let mut scoreboards = Vec::new();
+        while scoreboards.len() < 2 {
+            if let Message::Scores(scores) = protocol::read_message(&mut clients[2]).unwrap() {
+                scoreboards.push(scores);
+            }
+        }
+        let last: Vec<(&str, u16)> = scoreboards[1]
+            .iter()
+            .map(|(name, score)| (name.as_str(), *score))
+            .collect();
+        assert_eq!(last, [("ann", 4), ("bo", 5), ("cy", 3)]);
+    }
+
+    // Scores after a hand between two teams that finished in the given order
+    fn team_scores(order: &[usize]) -> Vec<u16> {
+        let rules = RuleSet {
+            teams: true,
+            ..RuleSet::new()
+        };
+        let (mut game, _clients) = table(&["ann", "bo", "cy", "dee"], rules);
+        game.assign_teams();
+        game.finished = order
+            .iter()
+            .map(|index| game.players_streams[*index].clone())
+            .collect();
+        game.score_hand();
+        game.players_streams
+            .iter()
+            .map(|player| player.score)
+            .collect()
+    }
+
+    #[test]
+    fn partners_finishing_first_and_second_sweep() {
+        // ann and cy play against bo and dee, and score 3 + 2 and the bonus of 3
+        assert_eq!(team_scores(&[0, 2, 1, 3]), [8, 1, 8, 1]);
+    }
+
+    #[test]
+    fn split_finishes_add_up_each_teams_places() {
+        // ann and cy score 3 + 1, bo and dee 2 + 0
+        assert_eq!(team_scores(&[0, 1, 2, 3]), [4, 2, 4, 2]);
+        // bo and dee score 3 + 1, cy and ann 2 + 0
+        assert_eq!(team_scores(&[1, 2, 3, 0]), [2, 4, 2, 4]);
+    }
+
+    #[test]
+    fn timeout_only_plays_the_opening_card_if_held() {
+        let (mut game, _clients) = table(&["ann", "bo"], RuleSet::new());
+        let three = Card::new(Suit::Hearts, Rank::Three);
+        let five = Card::new(Suit::Spades, Rank::Five);
+        give_hand(
+            &mut game,
+            0,
+            &[three.clone(), Card::new(Suit::Hearts, Rank::Four)],
+        );
+        give_hand(
+            &mut game,
+            1,
+            &[Card::new(Suit::Spades, Rank::Six), five.clone()],
+        );
+        game.opening_card = Some(three.clone());
+
+        let ann = game.players_streams[0].clone();
+        assert!(game.timeout_play(&ann, true).cards == vec![three]);
+        // The lead passed on without the opening card, e.g. because ann forfeited
+        let bo = game.players_streams[1].clone();
+        assert!(game.timeout_play(&bo, true).cards == vec![five]);
+    }
+
+    #[test]
+    fn both_jokers_exempt_from_tribute() {
+        let (mut game, _clients) = table(&["ann", "bo"], RuleSet::new());
+        let hand = [
+            Card::new(Suit::Red, Rank::Joker),
+            Card::new(Suit::Black, Rank::Joker),
+        ];
+        give_hand(&mut game, 0, &[Card::new(Suit::Hearts, Rank::Four)]);
+        give_hand(&mut game, 1, &hand);
+
+        // Nobody is asked for cards, so this returns without any client answering
+        let standings: Vec<Player> = game.players_streams.iter().cloned().collect();
+        game.pay_tribute(&standings);
+        assert!(game.players_streams[1].hand.contains_cards(&hand));
+    }
+}
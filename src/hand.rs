@@ -18,4 +18,51 @@ impl Hand {
     pub fn sort(&mut self) {
         self.cards.sort_unstable();
     }
+
+    // Whether every card can be matched to a different card in the hand
+    pub fn contains_cards(&self, cards: &[card::Card]) -> bool {
+        let mut remaining = self.cards.clone();
+        cards
+            .iter()
+            .all(|card| match remaining.iter().position(|c| c == card) {
+                Some(index) => {
+                    remaining.swap_remove(index);
+                    true
+                }
+                None => false,
+            })
+    }
+
+    // The n best cards in the hand, highest first
+    pub fn best_cards(&self, n: usize) -> Vec<card::Card> {
+        let mut cards = self.cards.clone();
+        cards.sort_by(|a, b| b.cmp_suit_tie_break(a));
+        cards.truncate(n);
+        cards
+    }
+
+    // The n lowest cards in the hand, lowest first
+    pub fn lowest_cards(&self, n: usize) -> Vec<card::Card> {
+        let mut cards = self.cards.clone();
+        cards.sort_by(|a, b| a.cmp_suit_tie_break(b));
+        cards.truncate(n);
+        cards
+    }
+
+    // Whether the hand holds both a red and a black joker
+    pub fn has_both_jokers(&self) -> bool {
+        [card::Suit::Red, card::Suit::Black].iter().all(|suit| {
+            self.cards
+                .iter()
+                .any(|card| card.rank == card::Rank::Joker && card.suit == *suit)
+        })
+    }
+
+    pub fn remove_cards(&mut self, cards: &[card::Card]) {
+        for card in cards {
+            if let Some(index) = self.cards.iter().position(|c| c == card) {
+                self.cards.remove(index);
+            }
+        }
+    }
 }
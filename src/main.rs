@@ -2,6 +2,7 @@ pub mod card;
 pub mod client;
 pub mod deck;
 pub mod display;
+pub mod fair_deal;
 pub mod game_client;
 pub mod game_server;
 pub mod hand;
@@ -9,23 +10,31 @@ pub mod logger;
 pub mod play;
 pub mod player;
 pub mod player_client;
+pub mod protocol;
 pub mod round;
+pub mod rules;
 pub mod server;
+pub mod websocket;
 
 use std::{
     collections::VecDeque,
-    net::IpAddr,
+    net::{IpAddr, TcpListener},
     num::IntErrorKind,
+    str::FromStr,
     sync::{Arc, Mutex},
     thread,
     time::{Duration, Instant},
 };
 
-use display::{announce_top_left, CheckBox, Display, InputBox, Renderable};
+use card::Rank;
+use deck::{Composition, Leftover};
+use display::{announce_top_left, CheckBox, Display, InputBox, Renderable, Warning};
 use game_client::GameClient;
 use game_server::GameServer;
 use logger::Logger;
 use player::Player;
+use protocol::Message;
+use rules::{ForfeitPolicy, MatchGoal, MatchRules, OpeningCard, RuleSet};
 
 fn main() {
     // TODO change this to launch arg
@@ -40,8 +49,46 @@ fn main() {
             let mut display = Display::new();
             let logger = Arc::new(Mutex::new(Logger::new()));
             display.add_renderable(Arc::clone(&logger));
+            let rules = choose_rules(&mut display);
+            let match_rules = loop {
+                let match_rules = choose_match_rules(&mut display);
+                match match_rules.check() {
+                    Ok(()) => break match_rules,
+                    Err(e) => display.add_renderable(Arc::new(Mutex::new(Warning::new(
+                        e,
+                        Duration::new(5, 0),
+                    )))),
+                }
+            };
+            let mut composition = loop {
+                let composition = choose_composition(&mut display);
+                match composition.check() {
+                    Ok(()) => break composition,
+                    Err(e) => display.add_renderable(Arc::new(Mutex::new(Warning::new(
+                        e,
+                        Duration::new(5, 0),
+                    )))),
+                }
+            };
             let mut server = server::Server::new(logger.clone());
-            server.accept_players();
+            // Browsers can join from a page served on the next port. The table still opens
+            // for terminal players if the port is taken.
+            let web_listener =
+                if input_flag(&mut display, "Let browsers join from http://127.0.0.1:9142?", false) {
+                    match TcpListener::bind("127.0.0.1:9142") {
+                        Ok(web_listener) => Some(web_listener),
+                        Err(e) => {
+                            display.add_renderable(Arc::new(Mutex::new(Warning::new(
+                                format!("Browsers cannot join: {e}"),
+                                Duration::new(5, 0),
+                            ))));
+                            None
+                        }
+                    }
+                } else {
+                    None
+                };
+            server.accept_players(web_listener);
 
             let mut player_names: Vec<String> = Vec::new();
             let mut refresh = true;
@@ -106,11 +153,22 @@ fn main() {
                 .iter()
                 .map(|(first, _, _)| first.clone())
                 .collect();
-            let deck = deck::Deck::new((players.len() / 4 + 1).try_into().unwrap());
-            let game = GameServer::new(server, players, deck, logger);
+            // The host picks again if the deck cannot deal everyone who joined a card
+            while let Err(e) = composition.check_table(players.len()) {
+                display.add_renderable(Arc::new(Mutex::new(Warning::new(
+                    e,
+                    Duration::new(5, 0),
+                ))));
+                composition = choose_composition(&mut display);
+            }
+            composition.num_decks = composition.decks_for(players.len());
+            let deck = deck::Deck::with_composition(composition);
+            let mut game = GameServer::new(server, players, deck, logger, rules, match_rules);
+            game.start_game();
+            game.end_game();
         }
         1 => {
-            let mut client: Result<client::Client, &'static str>;
+            let mut client: Result<client::Client, String>;
             let mut display = display::Display::new();
             let logger = Arc::new(Mutex::new(Logger::new()));
             let mut player: player::Player;
@@ -130,7 +188,7 @@ fn main() {
                         }
                     };
 
-                    if !name.contains('/') {
+                    if protocol::is_valid_name(&name) {
                         logger
                             .lock()
                             .unwrap()
@@ -226,13 +284,16 @@ fn main() {
 
             loop {
                 if start_game.lock().unwrap().checked {
-                    client.send("go\0");
+                    client.send(Message::Ready);
+                    break;
                 }
                 display.update();
             }
 
             // wait for game start message
-            // let mut game = GameClient::new(player, client, players, logger, display);
+            let mut game = GameClient::new(player, client, VecDeque::new(), logger, display);
+            game.wait_for_start();
+            game.end_game();
         }
         _ => {}
     }
@@ -271,6 +332,192 @@ fn main() {
     // game.end_game();
 }
 
+// Ask the host for the house rules, keeping the default for anything left empty
+fn choose_rules(display: &mut Display) -> RuleSet {
+    let default = RuleSet::new();
+    RuleSet {
+        min_straight_length: input_setting(
+            display,
+            "Minimum straight length",
+            default.min_straight_length,
+        ),
+        twos_in_straights: input_flag(display, "2s in straights?", default.twos_in_straights),
+        jokers_in_straights: input_flag(
+            display,
+            "Jokers in straights?",
+            default.jokers_in_straights,
+        ),
+        matching_length: input_flag(
+            display,
+            "Plays must match the length of the last play?",
+            default.matching_length,
+        ),
+        bombs: input_flag(display, "Bombs?", default.bombs),
+        jokers_wild: input_flag(display, "Jokers wild?", default.jokers_wild),
+        suit_tie_break: input_flag(
+            display,
+            "Break ties between equal ranks by suit?",
+            default.suit_tie_break,
+        ),
+        tribute: input_setting(
+            display,
+            "Cards paid as tribute by the last player (0-2)",
+            default.tribute,
+        )
+        .min(2),
+        turn_time: input_setting(
+            display,
+            "Seconds per move, 0 for no limit",
+            default.turn_time,
+        ),
+        time_bank: input_setting(
+            display,
+            "Seconds in each player's time bank, 0 to use the per move limit",
+            default.time_bank,
+        ),
+        increment: input_setting(
+            display,
+            "Seconds added to the time bank per move",
+            default.increment,
+        ),
+        forfeit: if input_flag(
+            display,
+            "Forfeit the hand when the time bank runs out?",
+            default.forfeit == ForfeitPolicy::Forfeit,
+        ) {
+            ForfeitPolicy::Forfeit
+        } else {
+            ForfeitPolicy::AutoMove
+        },
+        teams: input_flag(display, "Play in teams of two?", default.teams),
+        partner_ends_obligation: input_flag(
+            display,
+            "Pass automatically while your partner holds the lead?",
+            default.partner_ends_obligation,
+        ),
+        opening_card: if input_flag(
+            display,
+            "Lowest card leads instead of the 3 of hearts?",
+            default.opening_card == OpeningCard::Lowest,
+        ) {
+            OpeningCard::Lowest
+        } else {
+            OpeningCard::ThreeOfHearts
+        },
+        winner_leads: input_flag(
+            display,
+            "Last hand's winner leads the next hand?",
+            default.winner_leads,
+        ),
+        must_play_opening_card: input_flag(
+            display,
+            "First play must include the opening card?",
+            default.must_play_opening_card,
+        ),
+    }
+}
+
+fn choose_composition(display: &mut Display) -> Composition {
+    let default = Composition::new();
+    let num_decks = input_setting(
+        display,
+        "Number of decks, 0 for one per four players",
+        default.num_decks,
+    );
+    let jokers = input_setting(display, "Jokers per deck (0-2)", default.jokers);
+    let removed_ranks = input_answer(display, "Ranks to remove, e.g. 3 4 (none)".to_string())
+        .split_whitespace()
+        .filter_map(|rank| rank.parse().ok())
+        .filter(|rank| *rank != Rank::Joker)
+        .collect();
+    let leftover = if input_flag(
+        display,
+        "Show leftover cards to everyone?",
+        default.leftover == Leftover::Shown,
+    ) {
+        Leftover::Shown
+    } else {
+        Leftover::SetAside
+    };
+
+    Composition {
+        num_decks,
+        jokers,
+        removed_ranks,
+        leftover,
+    }
+}
+
+fn choose_match_rules(display: &mut Display) -> MatchRules {
+    let default = MatchRules::new();
+    let default_points: Vec<String> = default.points.iter().map(|p| p.to_string()).collect();
+    let default_points = default_points.join(" ");
+
+    let points_str = input_answer(
+        display,
+        format!("Points for each finishing place ({default_points})"),
+    );
+    let points: Vec<u16> = points_str
+        .split_whitespace()
+        .filter_map(|p| p.parse().ok())
+        .collect();
+
+    let goal = if input_flag(display, "Play a fixed number of hands?", false) {
+        MatchGoal::Hands(input_setting(display, "Number of hands", 5).max(1))
+    } else {
+        MatchGoal::TargetScore(input_setting(display, "Target score", 10))
+    };
+    let sweep_bonus = input_setting(
+        display,
+        "Bonus for a team finishing first and second",
+        default.sweep_bonus,
+    );
+
+    MatchRules {
+        points: if points.is_empty() {
+            default.points
+        } else {
+            points
+        },
+        goal,
+        sweep_bonus,
+    }
+}
+
+// Read a line from a new input box, treating Esc as an empty answer
+fn input_answer(display: &mut Display, prompt: String) -> String {
+    let input = Arc::new(Mutex::new(InputBox::new(prompt)));
+    display.add_renderable(Arc::clone(&input));
+    let answer = loop {
+        display.update();
+        if let Some(answer) = &input.lock().unwrap().output {
+            break answer.to_string();
+        }
+    };
+    answer.trim_matches('\0').trim().to_string()
+}
+
+fn input_setting<T>(display: &mut Display, prompt: &str, default: T) -> T
+where
+    T: FromStr + std::fmt::Display,
+{
+    input_answer(display, format!("{prompt} ({default})"))
+        .parse()
+        .unwrap_or(default)
+}
+
+fn input_flag(display: &mut Display, prompt: &str, default: bool) -> bool {
+    let default_str = if default { "y" } else { "n" };
+    match input_answer(display, format!("{prompt} y/n ({default_str})"))
+        .to_lowercase()
+        .as_str()
+    {
+        "y" | "yes" => true,
+        "n" | "no" => false,
+        _ => default,
+    }
+}
+
 fn input_u32(prompt: String, subject: String) -> u32 {
     let mut line = String::new();
     loop {
@@ -2,13 +2,22 @@ use core::fmt;
 
 use crate::card;
 use crate::card::Card;
+use crate::hand;
 use crate::player::{self, Player};
+use crate::rules::RuleSet;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashSet;
+use std::str::FromStr;
+use strum::IntoEnumIterator;
 
+#[derive(Clone)]
 pub struct Play {
     pub class: Class,
     pub rank: card::Rank,
     pub cards: Vec<card::Card>,
     pub player: player::Player,
+    pub rules: RuleSet,
+    pub wildcards: Vec<card::Rank>, // Rank each joker in cards stands for, in order
 }
 
 impl Play {
@@ -21,6 +30,56 @@ impl Play {
             rank,
             cards,
             player,
+            rules: RuleSet::new(),
+            wildcards: Vec::new(),
+        }
+    }
+
+    // A play of the cards sent by a client, with the ranks its jokers stand for in order. The
+    // jokers keep those ranks until the table's rules are set.
+    pub fn with_wildcards(
+        player: player::Player,
+        cards: Vec<card::Card>,
+        wildcards: Vec<card::Rank>,
+    ) -> Result<Self, ParsePlayError> {
+        if cards.is_empty() {
+            return Err(ParsePlayError::MissingCards);
+        }
+        let jokers = cards
+            .iter()
+            .filter(|card| card.rank == card::Rank::Joker)
+            .count();
+        if wildcards.len() > jokers {
+            return Err(ParsePlayError::TooManyWildcards);
+        }
+        let mut play = Play::new(player);
+        if wildcards.is_empty() {
+            play.set_cards(cards);
+        } else {
+            play.cards = cards;
+            play.set_wildcards(wildcards);
+        }
+        Ok(play)
+    }
+
+    // A play without cards, skipping the player's turn
+    pub fn pass(player: player::Player) -> Self {
+        let mut play = Play::new(player);
+        play.class = Class::Pass;
+        play
+    }
+
+    pub fn set_rules(&mut self, rules: RuleSet) {
+        self.rules = rules;
+        if self.class == Class::Pass {
+            return;
+        }
+        // Keep the ranks the jokers were resolved as, if they are still allowed to be wild
+        if rules.jokers_wild && !self.wildcards.is_empty() {
+            let wildcards = std::mem::take(&mut self.wildcards);
+            self.set_wildcards(wildcards);
+        } else {
+            self.identify_play();
         }
     }
 
@@ -35,153 +94,1070 @@ impl Play {
     }
 
     fn identify_play(&mut self) {
-        self.class = identify_class(&mut self.cards);
-        self.rank = identify_rank(&mut self.cards);
+        self.wildcards.clear();
+        if self.rules.jokers_wild && self.cards.iter().any(|card| card.rank == card::Rank::Joker) {
+            // Sort first so the jokers keep their order when resolving them
+            self.cards.sort_unstable();
+            match wildcard_interpretations(&self.cards, &self.rules)
+                .into_iter()
+                .max_by_key(|(class, rank, _)| (class.is_bomb(), *rank as usize))
+            {
+                Some((class, rank, wildcards)) => {
+                    self.class = class;
+                    self.rank = rank;
+                    self.wildcards = wildcards;
+                }
+                None => {
+                    self.class = Class::Invalid;
+                    self.rank = identify_rank(&mut self.cards);
+                }
+            }
+        } else {
+            self.class = identify_class(&mut self.cards, &self.rules);
+            self.rank = identify_rank(&mut self.cards);
+        }
+    }
+
+    // Check that the play can be made from the hand on top of the last play of the round
+    pub fn validate(&self, hand: &hand::Hand, last_play: Option<&Play>) -> Result<(), Rejection> {
+        // The player leading the round has to play something
+        if self.class == Class::Pass {
+            return match last_play {
+                Some(_) => Ok(()),
+                None => Err(Rejection::CannotPass),
+            };
+        }
+        if !hand.contains_cards(&self.cards) {
+            return Err(Rejection::NotOwned);
+        }
+        // Jokers can only stand for other ranks when the table plays them wild
+        if self.class == Class::Invalid || (!self.rules.jokers_wild && !self.wildcards.is_empty()) {
+            return Err(Rejection::InvalidClass);
+        }
+        match last_play {
+            Some(last_play) if !self.beats(last_play) => Err(Rejection::DoesNotBeat),
+            _ => Ok(()),
+        }
+    }
+
+    // Resolve the jokers as the given ranks, in the order they appear in the cards
+    fn set_wildcards(&mut self, wildcards: Vec<card::Rank>) {
+        self.wildcards = wildcards;
+        let mut resolved_cards = self.resolved_cards();
+        self.class = identify_class(&mut resolved_cards, &self.rules);
+        self.rank = identify_rank(&mut resolved_cards);
+    }
+
+    // The cards of the play, with each joker replaced by the rank it stands for
+    pub fn resolved_cards(&self) -> Vec<card::Card> {
+        let mut wildcards = self.wildcards.iter();
+        self.cards
+            .iter()
+            .map(|card| match card.rank {
+                card::Rank::Joker => match wildcards.next() {
+                    Some(rank) => Card::new(card.suit, *rank),
+                    None => card.clone(),
+                },
+                _ => card.clone(),
+            })
+            .collect()
+    }
+
+    // Resolve the jokers so that the play beats the given play, if any resolution does.
+    // Returns whether the play beats the other play.
+    pub fn resolve_against(&mut self, other: &Play) -> bool {
+        if self.beats(other) || !self.rules.jokers_wild || self.wildcards.is_empty() {
+            return self.beats(other);
+        }
+        let interpretation = wildcard_interpretations(&self.cards, &self.rules)
+            .into_iter()
+            .filter(|(class, rank, wildcards)| {
+                let mut resolved = Play::new(self.player.clone());
+                resolved.rules = self.rules;
+                resolved.cards = self.cards.clone();
+                resolved.class = *class;
+                resolved.rank = *rank;
+                resolved.wildcards = wildcards.clone();
+                resolved.beats(other)
+            })
+            .max_by_key(|(class, rank, _)| (class.is_bomb(), *rank as usize));
+        match interpretation {
+            Some((class, rank, wildcards)) => {
+                self.class = class;
+                self.rank = rank;
+                self.wildcards = wildcards;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Whether this play can be made on top of the previous play in the round
+    pub fn beats(&self, other: &Play) -> bool {
+        if self.class == Class::Invalid || self.class == Class::Pass {
+            return false;
+        }
+        let is_bomb = |play: &Play| self.rules.bombs && play.class.is_bomb();
+        match (is_bomb(self), is_bomb(other)) {
+            // Bombs beat every non-bomb, and nothing but a bomb beats a bomb
+            (true, false) => true,
+            (false, true) => false,
+            // Bigger bombs beat smaller ones, same-sized bombs are compared by rank
+            (true, true) => {
+                self.cards.len() > other.cards.len()
+                    || (self.cards.len() == other.cards.len()
+                        && self.rank as usize > other.rank as usize)
+            }
+            (false, false) => {
+                self.class == other.class
+                    && (!self.rules.matching_length || self.cards.len() == other.cards.len())
+                    && (self.rank as usize > other.rank as usize
+                        || (self.rules.suit_tie_break
+                            && self.rank == other.rank
+                            && self.beats_by_suit(other)))
+            }
+        }
+    }
+
+    // Whether the highest card of this play outranks the highest card of the other by suit
+    fn beats_by_suit(&self, other: &Play) -> bool {
+        let top_card = |play: &Play| {
+            play.resolved_cards()
+                .into_iter()
+                .max_by(|a, b| a.cmp_suit_tie_break(b))
+        };
+        match (top_card(self), top_card(other)) {
+            (Some(card), Some(other_card)) => card.cmp_suit_tie_break(&other_card).is_gt(),
+            _ => false,
+        }
     }
 }
 
-impl Into<String> for Play {
-    fn into(self) -> String {
-        let player_str = self.player.name;
+impl fmt::Display for Play {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:", self.player.name)?;
+        if self.class == Class::Pass {
+            return write!(f, " pass");
+        }
+        let mut wildcards = self.wildcards.iter();
+        for card in &self.cards {
+            write!(f, " {card}")?;
+            if card.rank == card::Rank::Joker {
+                if let Some(rank) = wildcards.next() {
+                    write!(f, " (as {rank})")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<Play> for String {
+    fn from(play: Play) -> Self {
+        let player_str = play.player.name;
+        if play.class == Class::Pass {
+            return format!("{player_str} pass");
+        }
 
+        // Jokers are followed by the rank they stand for, e.g. RO=5
+        let mut wildcards = play.wildcards.iter();
         let mut cards_str = "".to_string();
-        for card in self.cards {
-            cards_str.push_str(&card.to_string());
+        for card in play.cards {
+            let is_joker = card.rank == card::Rank::Joker;
+            cards_str.push(' ');
+            cards_str.push_str(&String::from(card));
+            if is_joker {
+                if let Some(rank) = wildcards.next() {
+                    cards_str.push('=');
+                    cards_str.push_str(rank.symbol());
+                }
+            }
         }
 
-        format!("{player_str} {cards_str}")
+        format!("{player_str}{cards_str}")
     }
 }
 
-impl From<String> for Play {
-    fn from(value: String) -> Self {
+// Parses a play as the player's name followed by the cards, or by "pass". Jokers can be
+// followed by the rank they stand for, e.g. RO=5.
+impl FromStr for Play {
+    type Err = ParsePlayError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
         let mut value = value.split_whitespace();
-        let player_str = value.next().unwrap();
+        let player_str = value.next().ok_or(ParsePlayError::MissingPlayer)?;
+        if value.clone().next() == Some("pass") {
+            return Ok(Play::pass(Player::new(player_str)));
+        }
         let mut cards: Vec<Card> = Vec::new();
-        while let Some(card_str) = value.next() {
-            cards.push(Card::from(card_str.to_string()));
+        let mut wildcards: Vec<card::Rank> = Vec::new();
+        for card_str in value {
+            match card_str.split_once('=') {
+                Some((card_str, rank_str)) => {
+                    let card: Card = card_str.parse()?;
+                    if card.rank != card::Rank::Joker {
+                        return Err(ParsePlayError::NotAJoker(card_str.to_string()));
+                    }
+                    cards.push(card);
+                    wildcards.push(rank_str.parse()?);
+                }
+                None => cards.push(card_str.parse()?),
+            }
         }
-        let mut play = Play::new(Player::new(player_str));
-        play.set_cards(cards);
-        play
+        Play::with_wildcards(Player::new(player_str), cards, wildcards)
     }
 }
 
-pub fn identify_class(cards: &mut Vec<card::Card>) -> Class {
+// In JSON, a play is written as the player's name, the cards, and the ranks its jokers stand
+// for in order, e.g. {"player":"ann","cards":["3♥","RO"],"wildcards":["3"]}
+#[derive(Serialize, Deserialize)]
+struct PlayFields {
+    player: String,
+    cards: Vec<Card>,
+    #[serde(default)]
+    wildcards: Vec<card::Rank>,
+}
+
+impl Serialize for Play {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        PlayFields {
+            player: self.player.name.clone(),
+            cards: self.cards.clone(),
+            wildcards: self.wildcards.clone(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Play {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let fields = PlayFields::deserialize(deserializer)?;
+        if fields.player.is_empty() {
+            return Err(de::Error::custom(ParsePlayError::MissingPlayer));
+        }
+        Play::with_wildcards(Player::new(&fields.player), fields.cards, fields.wildcards)
+            .map_err(de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParsePlayError {
+    MissingPlayer,
+    MissingCards,
+    NotAJoker(String),
+    TooManyWildcards,
+    Card(card::ParseCardError),
+}
+
+impl From<card::ParseCardError> for ParsePlayError {
+    fn from(error: card::ParseCardError) -> Self {
+        ParsePlayError::Card(error)
+    }
+}
+
+impl fmt::Display for ParsePlayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParsePlayError::MissingPlayer => write!(f, "no player given"),
+            ParsePlayError::MissingCards => write!(f, "no cards given"),
+            ParsePlayError::NotAJoker(card) => {
+                write!(f, "only jokers can stand for another rank, not {card}")
+            }
+            ParsePlayError::TooManyWildcards => write!(f, "more wildcards than jokers given"),
+            ParsePlayError::Card(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for ParsePlayError {}
+
+pub fn identify_class(cards: &mut [card::Card], rules: &RuleSet) -> Class {
+    if cards.is_empty() {
+        return Class::Invalid;
+    }
+    if !homogenous(cards) {
+        return straight(cards, rules);
+    }
     match cards.len() {
         1 => Class::Single,
-        2 => {
-            if homogenous(cards.to_vec()) {
-                Class::Double
-            } else {
-                Class::Invalid
-            }
+        2 => Class::Double,
+        3 => Class::Triple,
+        4 => Class::Quad,
+        // Five to eight of a kind are only possible when playing with multiple decks
+        5 => Class::Quintuple,
+        6 => Class::Sextuple,
+        7 => Class::Septuple,
+        8 => Class::Octuple,
+        _ => Class::Invalid,
+    }
+}
+
+// Every valid way of resolving the jokers in the cards, as the class and rank of the
+// resulting play and the rank each joker stands for. A joker may also stand for itself. Only
+// sets and straights are valid classes, so rather than trying every combination of ranks the
+// jokers either all stand for one rank, or fill in the ranks a straight is missing.
+pub fn wildcard_interpretations(
+    cards: &[card::Card],
+    rules: &RuleSet,
+) -> Vec<(Class, card::Rank, Vec<card::Rank>)> {
+    let (jokers, naturals): (Vec<Card>, Vec<Card>) = cards
+        .iter()
+        .cloned()
+        .partition(|card| card.rank == card::Rank::Joker);
+    let ranks: Vec<card::Rank> = card::Rank::iter().collect();
+
+    // Sets, with every joker standing for the same rank
+    let mut candidates: Vec<Vec<card::Rank>> =
+        ranks.iter().map(|rank| vec![*rank; jokers.len()]).collect();
+
+    // Straights of every width that uses all the cards
+    for width in 1..=3 {
+        if !cards.len().is_multiple_of(width) {
+            continue;
         }
-        3 => {
-            if homogenous(cards.to_vec()) {
-                Class::Triple
-            } else {
-                Class::Invalid
+        let steps = cards.len() / width;
+        for straight_ranks in ranks.windows(steps) {
+            let mut unused: Vec<card::Rank> = naturals.iter().map(|card| card.rank).collect();
+            let mut missing = Vec::new();
+            for rank in straight_ranks {
+                for _ in 0..width {
+                    match unused.iter().position(|unused_rank| unused_rank == rank) {
+                        Some(index) => {
+                            unused.swap_remove(index);
+                        }
+                        None => missing.push(*rank),
+                    }
+                }
             }
-        }
-        4 => {
-            if homogenous(cards.to_vec()) {
-                Class::Quad
-            } else {
-                Class::Invalid
+            if unused.is_empty() && missing.len() == jokers.len() {
+                candidates.push(missing);
             }
         }
-        _ => straight(cards),
     }
+    candidates.sort_by_key(|wildcards| {
+        wildcards
+            .iter()
+            .map(|rank| *rank as usize)
+            .collect::<Vec<_>>()
+    });
+    candidates.dedup();
+
+    let mut interpretations = Vec::new();
+    for wildcards in candidates {
+        let mut resolved_cards = naturals.clone();
+        resolved_cards.extend(
+            jokers
+                .iter()
+                .zip(&wildcards)
+                .map(|(joker, rank)| Card::new(joker.suit, *rank)),
+        );
+        let class = identify_class(&mut resolved_cards, rules);
+        if class != Class::Invalid {
+            interpretations.push((class, identify_rank(&mut resolved_cards), wildcards));
+        }
+    }
+    interpretations
 }
 
-pub fn identify_rank(cards: &mut Vec<card::Card>) -> card::Rank {
-    // Sort and get the highest ranked card
+pub fn identify_rank(cards: &mut [card::Card]) -> card::Rank {
+    // Sort and get the highest ranked card, or the lowest rank if there are no cards
     cards.sort_unstable();
-    cards.last().unwrap().rank
+    cards.last().map_or(card::Rank::Three, |card| card.rank)
 }
 
-fn homogenous(cards: Vec<card::Card>) -> bool {
+fn homogenous(cards: &[card::Card]) -> bool {
     if cards.is_empty() {
         return false;
     } // cannot be homogenous if no cards
-    for card in &cards {
-        if *card != cards[0] {
-            return false;
-        }
-    }
-    true
+    cards.iter().all(|card| card.rank == cards[0].rank)
 }
 
-fn straight(cards: &mut Vec<card::Card>) -> Class {
-    if cards.len() < 5 {
+fn straight(cards: &mut [card::Card], rules: &RuleSet) -> Class {
+    if cards.len() < rules.min_straight_length.max(3) {
         return Class::Invalid;
     } // cannot be straight if not enough cards
     cards.sort_unstable(); // sort cards
 
-    if cards[2].rank as usize == cards[1].rank as usize
-        && cards[1].rank as usize == cards[2].rank as usize
+    if (!rules.twos_in_straights && cards.iter().any(|card| card.rank == card::Rank::Two))
+        || (!rules.jokers_in_straights && cards.iter().any(|card| card.rank == card::Rank::Joker))
     {
-        // check for triple straight
-        if cards.len() % 3 != 0 {
+        return Class::Invalid;
+    }
+
+    // Number of cards of each rank, e.g. 2 for a double straight
+    let width = cards
+        .iter()
+        .take_while(|card| card.rank == cards[0].rank)
+        .count();
+    if !cards.len().is_multiple_of(width) {
+        return Class::Invalid;
+    }
+
+    // Every step of the straight must be the same rank, one above the previous step
+    for (step, step_cards) in cards.chunks(width).enumerate() {
+        if !homogenous(step_cards) || step_cards[0].rank as usize != cards[0].rank as usize + step {
             return Class::Invalid;
         }
+    }
 
-        for i in 2..cards.len() / 3 {
-            if cards[i * 3 - 1].rank as usize != cards[i * 3 - 2].rank as usize
-                || cards[i * 3 - 2].rank as usize != cards[i * 3 - 3].rank as usize
-                || cards[i * 3 - 3].rank as usize - 1 != cards[i * 3 - 4].rank as usize
-            {
-                return Class::Invalid;
-            }
+    match width {
+        1 => Class::SingleStraight,
+        2 => Class::DoubleStraight,
+        3 => Class::TripleStraight,
+        _ => Class::Invalid,
+    }
+}
+
+// Every play that can be made from the hand on top of the last play of the round, ordered
+// from the least to the most valuable. Only one play is given per combination of ranks, using
+// the lowest cards of each rank. With the suit tie break, every card of the highest rank is also
+// tried as the top card, since it decides between plays of the same rank. With wild jokers,
+// jokers also top up sets and fill the gaps in straights.
+pub fn legal_plays(
+    hand: &hand::Hand,
+    last_play: Option<&Play>,
+    rules: &RuleSet,
+) -> Vec<Vec<card::Card>> {
+    let mut cards = hand.cards.clone();
+    cards.sort_unstable();
+    let (jokers, naturals): (Vec<Card>, Vec<Card>) = if rules.jokers_wild {
+        cards
+            .into_iter()
+            .partition(|card| card.rank == card::Rank::Joker)
+    } else {
+        (Vec::new(), cards)
+    };
+
+    // Cards of the hand grouped by rank, in ascending order
+    let mut groups: Vec<Vec<card::Card>> = Vec::new();
+    for card in naturals {
+        match groups.last_mut() {
+            Some(group) if group[0].rank == card.rank => group.push(card),
+            _ => groups.push(vec![card]),
         }
-        return Class::TripleStraight;
     }
+    let group_of = |rank: card::Rank| {
+        groups
+            .iter()
+            .find(|group| group[0].rank == rank)
+            .map_or(&[][..], |group| group.as_slice())
+    };
 
-    if cards[1].rank as usize == cards[0].rank as usize {
-        // check for double straight
-        // make sure length is even
-        if cards.len() % 2 != 0 {
-            return Class::Invalid;
+    // The ways of taking n cards of a rank from its group, topped up with wild jokers
+    let take = |group: &[Card], n: usize, jokers: &[Card]| -> Vec<Vec<Card>> {
+        let natural = group.len().min(n);
+        let wild = n - natural;
+        if wild > jokers.len() {
+            return Vec::new();
+        }
+        let mut choices = vec![group[..natural].to_vec()];
+        if rules.suit_tie_break && natural > 0 {
+            choices.extend((natural..group.len()).map(|top| {
+                let mut choice = group[..natural - 1].to_vec();
+                choice.push(group[top].clone());
+                choice
+            }));
         }
+        for choice in &mut choices {
+            choice.extend_from_slice(&jokers[..wild]);
+        }
+        choices
+    };
+    let sets = |size: usize| {
+        let mut sets: Vec<Vec<Card>> = groups
+            .iter()
+            .flat_map(|group| take(group, size, &jokers))
+            .collect();
+        if size <= jokers.len() {
+            sets.push(jokers[..size].to_vec());
+        }
+        sets
+    };
 
-        for i in 2..cards.len() / 2 {
-            if cards[i * 2 - 1].rank as usize != cards[i * 2 - 2].rank as usize
-                || cards[i * 2 - 2].rank as usize - 1usize != cards[i * 2 - 3].rank as usize
-            {
-                return Class::Invalid;
+    let mut candidates: Vec<Vec<card::Card>> = Vec::new();
+
+    // Singles, doubles and triples
+    for size in 1..=3 {
+        candidates.extend(sets(size));
+    }
+
+    // Single, double and triple straights of every length
+    let ranks: Vec<card::Rank> = card::Rank::iter().collect();
+    for width in 1..=3 {
+        let min_steps = rules.min_straight_length.max(3).div_ceil(width).max(2);
+        for start in 0..ranks.len() {
+            let mut straight_cards = Vec::new();
+            let mut wild_used = 0;
+            for (step, rank) in ranks[start..].iter().enumerate() {
+                let group = group_of(*rank);
+                let choices = take(group, width, &jokers[wild_used..]);
+                let Some(lowest) = choices.first().cloned() else {
+                    break;
+                };
+                if step + 1 >= min_steps {
+                    for choice in choices {
+                        let mut candidate = straight_cards.clone();
+                        candidate.extend(choice);
+                        candidates.push(candidate);
+                    }
+                }
+                wild_used += width - group.len().min(width);
+                straight_cards.extend(lowest);
             }
         }
-        return Class::DoubleStraight;
     }
 
-    if cards[1].rank as usize - 1 == cards[0].rank as usize {
-        // check for single straight
-        for i in 2..cards.len() {
-            if cards[i].rank as usize - 1 != cards[i - 1].rank as usize {
-                return Class::Invalid;
-            }
+    // Bombs, from the smallest to the biggest
+    for size in 4..=8 {
+        candidates.extend(sets(size));
+    }
+
+    // Jokers can make the same cards out of different ranks
+    let mut seen = HashSet::new();
+    candidates.retain(|candidate| {
+        let mut cards = candidate.clone();
+        cards.sort_unstable();
+        seen.insert(cards)
+    });
+
+    candidates
+        .into_iter()
+        .filter(|candidate| {
+            let mut play = Play::new(Player::new(""));
+            play.rules = *rules;
+            play.set_cards(candidate.clone());
+            play.class != Class::Invalid
+                && match last_play {
+                    Some(last_play) => play.resolve_against(last_play),
+                    None => true,
+                }
+        })
+        .collect()
+}
+
+// Reasons the server can refuse a play
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rejection {
+    Malformed,
+    NotOwned,
+    InvalidClass,
+    DoesNotBeat,
+    CannotPass,
+    WrongCount,
+    MissingOpeningCard,
+}
+
+impl Rejection {
+    // Code used for the rejection when sending it over the network
+    pub fn code(&self) -> &'static str {
+        match self {
+            Rejection::Malformed => "malformed",
+            Rejection::NotOwned => "owned",
+            Rejection::InvalidClass => "class",
+            Rejection::DoesNotBeat => "beat",
+            Rejection::CannotPass => "pass",
+            Rejection::WrongCount => "count",
+            Rejection::MissingOpeningCard => "opening",
         }
-        return Class::SingleStraight;
     }
 
-    Class::Invalid
+    pub fn from_code(code: &str) -> Option<Rejection> {
+        [
+            Rejection::Malformed,
+            Rejection::NotOwned,
+            Rejection::InvalidClass,
+            Rejection::DoesNotBeat,
+            Rejection::CannotPass,
+            Rejection::WrongCount,
+            Rejection::MissingOpeningCard,
+        ]
+        .into_iter()
+        .find(|rejection| rejection.code() == code)
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rejection::Malformed => write!(f, "The server could not read that play."),
+            Rejection::NotOwned => write!(f, "You do not hold those cards."),
+            Rejection::InvalidClass => write!(f, "Please make a valid move."),
+            Rejection::DoesNotBeat => write!(f, "That play does not beat the last play."),
+            Rejection::CannotPass => write!(f, "You are leading the round and cannot pass."),
+            Rejection::WrongCount => write!(f, "Please choose the number of cards asked for."),
+            Rejection::MissingOpeningCard => {
+                write!(f, "The first play must include the opening card.")
+            }
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Class {
     Invalid,
+    Pass,
     Single,
     Double,
     Triple,
     Quad,
+    Quintuple,
+    Sextuple,
+    Septuple,
+    Octuple,
     SingleStraight,
     DoubleStraight,
     TripleStraight,
 }
 
+impl Class {
+    // Bombs are sets of the same rank that can be played on top of any other class
+    pub fn is_bomb(&self) -> bool {
+        matches!(
+            self,
+            Class::Quad | Class::Quintuple | Class::Sextuple | Class::Septuple | Class::Octuple
+        )
+    }
+}
+
 impl fmt::Display for Class {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deck::Deck;
+
+    // Take the first n cards of the given rank out of a freshly built deck
+    fn n_of_a_kind(num_decks: u32, rank: card::Rank, n: usize) -> Play {
+        let cards: Vec<Card> = Deck::new(num_decks)
+            .cards
+            .into_iter()
+            .filter(|card| card.rank == rank)
+            .take(n)
+            .collect();
+        assert_eq!(
+            cards.len(),
+            n,
+            "deck does not hold {n} cards of rank {rank}"
+        );
+
+        let mut play = Play::new(Player::new("test"));
+        play.set_cards(cards);
+        play
+    }
+
+    fn play_of_ranks(ranks: &[card::Rank]) -> Play {
+        let mut play = Play::new(Player::new("test"));
+        play.set_cards(
+            ranks
+                .iter()
+                .map(|rank| Card::new(card::Suit::Spades, *rank))
+                .collect(),
+        );
+        play
+    }
+
+    #[test]
+    fn joker_fills_gap_in_straight() {
+        use card::Rank::*;
+        let mut play = play_of_ranks(&[Four, Five, Seven, Eight]);
+        play.add_card(Card::new(card::Suit::Red, Joker));
+        assert_eq!(play.class, Class::Invalid);
+
+        play.set_rules(RuleSet {
+            jokers_wild: true,
+            ..RuleSet::new()
+        });
+        assert_eq!(play.class, Class::SingleStraight);
+        assert_eq!(play.rank, Eight);
+        assert_eq!(play.wildcards, vec![Six]);
+    }
+
+    #[test]
+    fn many_jokers_resolve_quickly() {
+        use card::Rank::*;
+        let rules = RuleSet {
+            jokers_wild: true,
+            ..RuleSet::new()
+        };
+        let mut cards: Vec<Card> = Deck::new(8)
+            .cards
+            .into_iter()
+            .filter(|card| card.rank == Joker)
+            .collect();
+        cards.extend(play_of_ranks(&[Three, Four, Five, Six]).cards);
+
+        let start = std::time::Instant::now();
+        let mut play = Play::new(Player::new("test"));
+        play.rules = rules;
+        play.set_cards(cards.clone());
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+        // Sixteen jokers fill out a double straight from 3 up to queens
+        assert_eq!(play.class, Class::DoubleStraight);
+        assert_eq!(play.rank, Queen);
+
+        // Forty jokers make no play, and still take no time to rule out
+        let mut jokers = cards.clone();
+        jokers.extend(cards.iter().filter(|card| card.rank == Joker).cloned());
+        jokers.extend(cards.into_iter().filter(|card| card.rank == Joker).take(8));
+        assert_eq!(jokers.len(), 44);
+        let start = std::time::Instant::now();
+        play.set_cards(jokers);
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+        assert_eq!(play.class, Class::Invalid);
+    }
+
+    #[test]
+    fn wildcards_survive_round_trip() {
+        use card::Rank::*;
+        let mut play = play_of_ranks(&[Nine, Nine]);
+        play.add_card(Card::new(card::Suit::Black, Joker));
+        play.set_rules(RuleSet {
+            jokers_wild: true,
+            ..RuleSet::new()
+        });
+        assert_eq!(play.class, Class::Triple);
+        assert!(play.to_string().ends_with("Black Joker (as Nine)"));
+
+        let play_str: String = play.into();
+        let parsed: Play = play_str.parse().unwrap();
+        assert_eq!(parsed.class, Class::Triple);
+        assert_eq!(parsed.rank, Nine);
+        assert_eq!(parsed.wildcards, vec![Nine]);
+    }
+
+    #[test]
+    fn wildcards_follow_the_table_rules() {
+        let mut hand = hand::Hand::new();
+        hand.add_card(Card::new(card::Suit::Spades, card::Rank::Five));
+        hand.add_card(Card::new(card::Suit::Red, card::Rank::Joker));
+
+        // A joker sent as a five is only a pair of fives if jokers are wild
+        let mut play: Play = "test 5♠ RO=5".parse().unwrap();
+        assert_eq!(play.validate(&hand, None), Err(Rejection::InvalidClass));
+        play.set_rules(RuleSet::new());
+        assert!(play.wildcards.is_empty());
+        assert_eq!(play.validate(&hand, None), Err(Rejection::InvalidClass));
+
+        let mut play: Play = "test 5♠ RO=5".parse().unwrap();
+        play.set_rules(RuleSet {
+            jokers_wild: true,
+            ..RuleSet::new()
+        });
+        assert_eq!(play.class, Class::Double);
+        assert_eq!(play.validate(&hand, None), Ok(()));
+    }
+
+    #[test]
+    fn legal_plays_beat_the_last_play() {
+        use card::Rank::*;
+        let mut hand = hand::Hand::new();
+        for rank in [
+            Three, Four, Five, Six, Seven, Seven, Eight, Eight, Eight, Eight,
+        ] {
+            hand.add_card(Card::new(card::Suit::Hearts, rank));
+        }
+
+        let leading = legal_plays(&hand, None, &RuleSet::new());
+        assert!(leading.contains(&vec![Card::new(card::Suit::Hearts, Three)]));
+        assert!(leading.iter().any(|cards| {
+            cards.iter().map(|card| card.rank).collect::<Vec<_>>()
+                == vec![Three, Four, Five, Six, Seven, Eight]
+        }));
+
+        let pair_of_sevens = play_of_ranks(&[Seven, Seven]);
+        let responses = legal_plays(&hand, Some(&pair_of_sevens), &RuleSet::new());
+        let response_ranks: Vec<Vec<card::Rank>> = responses
+            .iter()
+            .map(|cards| cards.iter().map(|card| card.rank).collect())
+            .collect();
+        assert_eq!(response_ranks, vec![vec![Eight, Eight], vec![Eight; 4]]);
+    }
+
+    #[test]
+    fn legal_plays_try_every_suit_with_the_suit_tie_break() {
+        use card::Rank::*;
+        let rules = RuleSet {
+            suit_tie_break: true,
+            ..RuleSet::new()
+        };
+        let mut hand = hand::Hand::new();
+        hand.add_card(Card::new(card::Suit::Diamonds, Seven));
+        hand.add_card(Card::new(card::Suit::Spades, Seven));
+
+        // Only the seven of spades beats the seven of hearts
+        let mut seven_of_hearts = Play::new(Player::new("test"));
+        seven_of_hearts.rules = rules;
+        seven_of_hearts.set_cards(vec![Card::new(card::Suit::Hearts, Seven)]);
+        let responses = legal_plays(&hand, Some(&seven_of_hearts), &rules);
+        assert!(responses == vec![vec![Card::new(card::Suit::Spades, Seven)]]);
+
+        // Without the tie break neither beats it
+        assert!(legal_plays(&hand, Some(&seven_of_hearts), &RuleSet::new()).is_empty());
+    }
+
+    #[test]
+    fn legal_plays_use_wild_jokers() {
+        use card::Rank::*;
+        let rules = RuleSet {
+            jokers_wild: true,
+            ..RuleSet::new()
+        };
+        let mut hand = hand::Hand::new();
+        for rank in [Four, Five, Seven, Eight, Nine, Nine] {
+            hand.add_card(Card::new(card::Suit::Hearts, rank));
+        }
+        let joker = Card::new(card::Suit::Red, Joker);
+        hand.add_card(joker.clone());
+
+        let leading = legal_plays(&hand, None, &rules);
+        let ranks = |cards: &Vec<Card>| {
+            let mut ranks: Vec<card::Rank> = cards.iter().map(|card| card.rank).collect();
+            ranks.sort_by_key(|rank| *rank as usize);
+            ranks
+        };
+        // The joker fills the gap in the straight, and makes a triple of nines
+        assert!(leading
+            .iter()
+            .any(|cards| ranks(cards) == vec![Four, Five, Seven, Eight, Joker]));
+        assert!(leading
+            .iter()
+            .any(|cards| ranks(cards) == vec![Nine, Nine, Joker]));
+
+        // Without wild jokers there is no straight and no triple
+        let plain = legal_plays(&hand, None, &RuleSet::new());
+        assert!(plain.iter().all(|cards| cards.len() < 3));
+
+        let triple_of_eights = play_of_ranks(&[Eight, Eight, Eight]);
+        let responses = legal_plays(&hand, Some(&triple_of_eights), &rules);
+        assert!(responses.iter().all(|cards| cards.contains(&joker)));
+        assert!(responses
+            .iter()
+            .any(|cards| ranks(cards) == vec![Nine, Nine, Joker]));
+    }
+
+    fn play_under(ranks: &[card::Rank], rules: RuleSet) -> Play {
+        let mut play = play_of_ranks(ranks);
+        play.set_rules(rules);
+        play
+    }
+
+    #[test]
+    fn bombs_can_be_turned_off() {
+        use card::Rank::*;
+        let rules = RuleSet {
+            bombs: false,
+            ..RuleSet::new()
+        };
+        let quad = play_under(&[Four, Four, Four, Four], rules);
+        let pair = play_under(&[Two, Two], rules);
+        assert_eq!(quad.class, Class::Quad);
+        assert!(!quad.beats(&pair));
+        assert!(!pair.beats(&quad));
+
+        // Without bombs a quad only beats a lower quad
+        assert!(play_under(&[Five, Five, Five, Five], rules).beats(&quad));
+        assert!(play_of_ranks(&[Four, Four, Four, Four]).beats(&play_of_ranks(&[Two, Two])));
+    }
+
+    #[test]
+    fn straights_follow_the_minimum_length() {
+        use card::Rank::*;
+        let short = [Eight, Nine, Ten];
+        assert_eq!(play_of_ranks(&short).class, Class::Invalid);
+        let rules = RuleSet {
+            min_straight_length: 3,
+            ..RuleSet::new()
+        };
+        assert_eq!(play_under(&short, rules).class, Class::SingleStraight);
+        assert_eq!(
+            play_under(&[Eight, Eight, Nine, Nine], rules).class,
+            Class::DoubleStraight
+        );
+
+        // Straights are never shorter than three cards
+        let rules = RuleSet {
+            min_straight_length: 2,
+            ..RuleSet::new()
+        };
+        assert_eq!(play_under(&[Eight, Nine], rules).class, Class::Invalid);
+    }
+
+    #[test]
+    fn twos_and_jokers_in_straights_can_be_turned_off() {
+        use card::Rank::*;
+        let with_two = [Jack, Queen, King, Ace, Two];
+        let mut with_joker: Vec<Card> = [Queen, King, Ace, Two]
+            .iter()
+            .map(|rank| Card::new(card::Suit::Hearts, *rank))
+            .collect();
+        with_joker.push(Card::new(card::Suit::Red, Joker));
+
+        let rules = RuleSet::new();
+        assert_eq!(play_of_ranks(&with_two).class, Class::SingleStraight);
+        assert_eq!(
+            identify_class(&mut with_joker.clone(), &rules),
+            Class::SingleStraight
+        );
+
+        let no_twos = RuleSet {
+            twos_in_straights: false,
+            ..RuleSet::new()
+        };
+        assert_eq!(play_under(&with_two, no_twos).class, Class::Invalid);
+        assert_eq!(
+            identify_class(&mut with_joker.clone(), &no_twos),
+            Class::Invalid
+        );
+
+        let no_jokers = RuleSet {
+            jokers_in_straights: false,
+            ..RuleSet::new()
+        };
+        assert_eq!(
+            play_under(&with_two, no_jokers).class,
+            Class::SingleStraight
+        );
+        assert_eq!(
+            identify_class(&mut with_joker.clone(), &no_jokers),
+            Class::Invalid
+        );
+
+        // A straight nobody can play under the rules beats nothing
+        let low = play_of_ranks(&[Ten, Jack, Queen, King, Ace]);
+        assert!(play_of_ranks(&with_two).beats(&low));
+        assert!(!play_under(&with_two, no_twos).beats(&low));
+    }
+
+    #[test]
+    fn parse_unicode_and_ascii_notation() {
+        use card::Rank::*;
+        let unicode: Play = "alice 0♥ J♥ Q♥ K♥ A♥#1".parse().unwrap();
+        let ascii: Play = "alice 10h jh qH KH AH".parse().unwrap();
+        assert_eq!(unicode.class, Class::SingleStraight);
+        assert_eq!(unicode.rank, Ace);
+        assert_eq!(unicode.cards[4].deck, 1);
+        assert_eq!(ascii.class, Class::SingleStraight);
+        assert_eq!(ascii.rank, Ace);
+
+        let jokers: Play = "bob RJ BO".parse().unwrap();
+        assert_eq!(jokers.class, Class::Double);
+        assert_eq!(jokers.rank, Joker);
+
+        assert_eq!(
+            "".parse::<Play>().err(),
+            Some(ParsePlayError::MissingPlayer)
+        );
+        assert_eq!(
+            "bob 3X".parse::<Play>().err(),
+            Some(ParsePlayError::Card(card::ParseCardError::UnknownSuit(
+                "X".to_string()
+            )))
+        );
+        assert_eq!(
+            "bob 1H".parse::<Play>().err(),
+            Some(ParsePlayError::Card(card::ParseCardError::UnknownRank(
+                "1".to_string()
+            )))
+        );
+        assert_eq!(
+            "bob 3♥=5".parse::<Play>().err(),
+            Some(ParsePlayError::NotAJoker("3♥".to_string()))
+        );
+    }
+
+    #[test]
+    fn no_cards_is_not_a_play() {
+        let mut play = Play::new(Player::new("test"));
+        play.set_cards(Vec::new());
+        assert_eq!(play.class, Class::Invalid);
+        assert_eq!(play.rank, card::Rank::Three);
+
+        play.set_rules(RuleSet {
+            jokers_wild: true,
+            ..RuleSet::new()
+        });
+        assert_eq!(play.class, Class::Invalid);
+    }
+
+    #[test]
+    fn two_decks_allow_up_to_eight_of_a_kind() {
+        assert_eq!(n_of_a_kind(2, card::Rank::Seven, 5).class, Class::Quintuple);
+        assert_eq!(n_of_a_kind(2, card::Rank::Seven, 6).class, Class::Sextuple);
+        assert_eq!(n_of_a_kind(2, card::Rank::Seven, 7).class, Class::Septuple);
+        assert_eq!(n_of_a_kind(2, card::Rank::Seven, 8).class, Class::Octuple);
+    }
+
+    #[test]
+    fn three_decks_n_of_a_kind() {
+        let sextuple = n_of_a_kind(3, card::Rank::Ace, 6);
+        assert_eq!(sextuple.class, Class::Sextuple);
+        assert_eq!(sextuple.rank, card::Rank::Ace);
+        assert_eq!(n_of_a_kind(3, card::Rank::Ace, 8).class, Class::Octuple);
+        // Nine of a kind is not a class, and is not mistaken for a triple straight
+        assert_eq!(n_of_a_kind(3, card::Rank::Ace, 9).class, Class::Invalid);
+    }
+
+    #[test]
+    fn bigger_sets_beat_smaller_sets() {
+        let quad = n_of_a_kind(2, card::Rank::Two, 4);
+        let quintuple = n_of_a_kind(2, card::Rank::Three, 5);
+        let sextuple = n_of_a_kind(2, card::Rank::Three, 6);
+
+        assert!(quintuple.beats(&quad));
+        assert!(!quad.beats(&quintuple));
+        assert!(sextuple.beats(&quintuple));
+        assert!(!quintuple.beats(&sextuple));
+    }
+
+    #[test]
+    fn same_sized_sets_compare_by_rank() {
+        let low = n_of_a_kind(3, card::Rank::Four, 7);
+        let high = n_of_a_kind(3, card::Rank::King, 7);
+
+        assert!(high.beats(&low));
+        assert!(!low.beats(&high));
+        assert!(!low.beats(&n_of_a_kind(3, card::Rank::Four, 7)));
+    }
+
+    #[test]
+    fn bombs_beat_everything_but_bigger_bombs() {
+        use card::Rank::*;
+        let bomb = play_of_ranks(&[Three, Three, Three, Three]);
+        assert!(bomb.class.is_bomb());
+        for other in [
+            play_of_ranks(&[Two]),
+            play_of_ranks(&[Two, Two]),
+            play_of_ranks(&[Ace, Ace, Ace]),
+            play_of_ranks(&[Ten, Jack, Queen, King, Ace]),
+        ] {
+            assert!(bomb.beats(&other));
+            assert!(!other.beats(&bomb));
+        }
+
+        let higher = play_of_ranks(&[Four, Four, Four, Four]);
+        assert!(higher.beats(&bomb));
+        assert!(!bomb.beats(&higher));
+    }
+
+    #[test]
+    fn n_of_a_kind_beats_straights() {
+        use card::Rank::*;
+        let straight = play_of_ranks(&[Ten, Jack, Queen, King, Ace]);
+        assert_eq!(straight.class, Class::SingleStraight);
+
+        let quintuple = n_of_a_kind(2, Three, 5);
+        assert!(quintuple.beats(&straight));
+        assert!(!straight.beats(&quintuple));
+    }
+}
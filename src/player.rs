@@ -1,10 +1,13 @@
 use crate::hand;
+use std::time::Duration;
 
 #[derive(Clone)]
 pub struct Player {
     pub hand: hand::Hand,
     pub name: String,
     pub score: u16,
+    pub clock: Duration,     // Time left in the player's time bank
+    pub team: Option<usize>, // Partnership the player belongs to, when playing in teams
 }
 
 impl Player {
@@ -18,6 +21,8 @@ impl Player {
             hand,
             name: name.into(),
             score,
+            clock: Duration::ZERO,
+            team: None,
         }
     }
 }
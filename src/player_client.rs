@@ -1,10 +1,13 @@
 use crate::hand;
+use std::time::Duration;
 
 #[derive(Clone)]
 pub struct PlayerClient {
     pub num_cards: u16,
     pub name: String,
     pub score: u16,
+    pub clock: Option<Duration>, // Time left in the player's time bank, if playing with one
+    pub team: Option<usize>,
 }
 
 impl PlayerClient {
@@ -16,6 +19,8 @@ impl PlayerClient {
             num_cards: 0u16,
             name: name.into(),
             score: 0u16,
+            clock: None,
+            team: None,
         }
     }
 }
@@ -0,0 +1,877 @@
+use crate::card::Card;
+use crate::deck::Composition;
+use crate::play::{Class, Play, Rejection};
+use crate::rules::RuleSet;
+use crate::websocket;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use std::time::Duration;
+
+// Messages can be sent in two encodings. The native one sends every message as a frame: its
+// length as a big endian u32, followed by that many bytes of UTF-8 text starting with a single
+// character tag naming the message. The JSON one sends one object per line, for clients and
+// bots written in other languages, e.g.
+//     {"type":"prompt","data":{"time_left":30000}}
+//     {"type":"play","data":{"player":"ann","cards":["3♥","3♦"],"wildcards":[]}}
+// Cards and ranks are written in the same notation in both, and everything else as JSON
+// objects and strings. A client asks for JSON by listing the
+// json feature in its hello, which it can send in either encoding. The server answers in the
+// encoding of the hello, and once json is agreed every later message is JSON. Browsers send
+// the same JSON objects, one per WebSocket message.
+
+// Frames and lines longer than this are refused rather than allocated
+const MAX_FRAME_LENGTH: usize = 64 * 1024;
+
+// Bumped whenever a message changes in a way older builds cannot read
+pub const PROTOCOL_VERSION: u32 = 2;
+
+pub const JSON_FEATURE: &str = "json";
+
+// Optional features the server supports, agreed on during the handshake
+pub const FEATURES: &[&str] = &[JSON_FEATURE];
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum Message {
+    // Handshake. The client sends the hello first, and the server welcomes it with the
+    // features both sides support, or refuses it.
+    Hello {
+        version: u32,
+        name: String,
+        features: Vec<String>,
+    },
+    Welcome {
+        features: Vec<String>,
+    },
+    Refused(Refusal),
+
+    // Lobby
+    Heartbeat,
+    Ready, // The client wants the game to start
+
+    // Match setup
+    Rules(RuleSet),
+    Composition(Composition),
+    Teams(Vec<(String, usize)>),
+    Scores(Vec<(String, u16)>),
+
+    // Deal. Every client answers the commitment with fresh entropy, and the server then sends
+    // the entropy of every player it mixes into the deal.
+    Commitment(String),
+    Entropy(u64),
+    DealEntropy(Vec<(String, u64)>),
+    // Every player's seat with their card count, and the hand of the player it is sent to
+    Deal {
+        seats: Vec<(String, usize)>,
+        hand: Vec<Card>,
+    },
+    // Cards are only listed if they are shown
+    Leftover {
+        count: usize,
+        cards: Vec<Card>,
+    },
+    // No cards if `from` is exempt from the tribute
+    Tribute {
+        from: String,
+        to: String,
+        cards: Vec<Card>,
+    },
+    AskTribute {
+        count: usize,
+        to: String,
+    },
+    TributeReturn(Vec<Card>),
+    Leader {
+        name: String,
+        card: Option<Card>,
+    },
+    Reveal {
+        #[serde(with = "hex")]
+        secret: Vec<u8>,
+        order: Vec<String>,
+    },
+
+    // Rounds
+    NewRound,
+    Prompt {
+        #[serde(with = "optional_millis")]
+        time_left: Option<Duration>,
+    },
+    Play(Play),
+    Pass(String),
+    Reject(Rejection),
+    CardCounts(Vec<(String, usize)>),
+    Clocks(#[serde(with = "clock_millis")] Vec<(String, Duration)>),
+    WentOut {
+        place: usize,
+        name: String,
+    },
+    Forfeit(String),
+    RoundEnd,
+
+    // End of the hand and of the game
+    Standings(Vec<String>),
+    GameEnd {
+        winner: String,
+    },
+}
+
+impl Message {
+    pub fn encode(&self) -> String {
+        match self {
+            // The version always comes first, so that any build can read it
+            Message::Hello {
+                version,
+                name,
+                features,
+            } => format!("H{version} {} {name}", encode_features(features)),
+            Message::Welcome { features } => format!("C{}", encode_features(features)),
+            Message::Refused(refusal) => format!("N{}", refusal.code()),
+            Message::Heartbeat => "K".to_string(),
+            Message::Ready => "G".to_string(),
+            Message::Rules(rules) => {
+                let rules_str: String = (*rules).into();
+                format!("u{rules_str}")
+            }
+            Message::Composition(composition) => {
+                let composition_str: String = composition.clone().into();
+                format!("n{composition_str}")
+            }
+            Message::Teams(teams) => format!("a{}", encode_pairs(teams)),
+            Message::Scores(scores) => format!("c{}", encode_pairs(scores)),
+            Message::Commitment(commitment) => format!("h{commitment}"),
+            Message::Entropy(entropy) => format!("E{entropy}"),
+            Message::DealEntropy(entropy) => format!("d{}", encode_pairs(entropy)),
+            Message::Deal { seats, hand } => {
+                format!("s{};{}", encode_pairs(seats), encode_cards(hand))
+            }
+            Message::Leftover { count, cards } => format!("z{count} {}", encode_cards(cards)),
+            Message::Tribute { from, to, cards } => {
+                format!("t{from} {to} {}", encode_cards(cards))
+            }
+            Message::AskTribute { count, to } => format!("g{count} {to}"),
+            Message::TributeReturn(cards) => format!("b{}", encode_cards(cards)),
+            Message::Leader { name, card } => match card {
+                Some(card) => {
+                    let card_str: String = card.clone().into();
+                    format!("l{name} {card_str}")
+                }
+                None => format!("l{name}"),
+            },
+            Message::Reveal { secret, order } => {
+                format!("v{} {}", crate::fair_deal::to_hex(secret), order.join(" "))
+            }
+            Message::NewRound => "r".to_string(),
+            Message::Prompt { time_left } => match time_left {
+                Some(time_left) => format!("m{}", time_left.as_millis()),
+                None => "m".to_string(),
+            },
+            Message::Play(play) => {
+                let play_str: String = play.clone().into();
+                format!("p{play_str}")
+            }
+            Message::Pass(name) => format!("P{name}"),
+            Message::Reject(rejection) => format!("x{}", rejection.code()),
+            Message::CardCounts(counts) => format!("y{}", encode_pairs(counts)),
+            Message::Clocks(clocks) => {
+                let clocks: Vec<(String, u128)> = clocks
+                    .iter()
+                    .map(|(name, clock)| (name.clone(), clock.as_millis()))
+                    .collect();
+                format!("k{}", encode_pairs(&clocks))
+            }
+            Message::WentOut { place, name } => format!("w{place} {name}"),
+            Message::Forfeit(name) => format!("q{name}"),
+            Message::RoundEnd => "e".to_string(),
+            Message::Standings(names) => format!("f{}", names.join(" ")),
+            Message::GameEnd { winner } => format!("o{winner}"),
+        }
+    }
+
+    pub fn decode(text: &str) -> Result<Self, ProtocolError> {
+        let malformed = || ProtocolError::Malformed(text.to_string());
+        let mut chars = text.chars();
+        let tag = chars.next().ok_or_else(malformed)?;
+        let body = chars.as_str();
+
+        let message = match tag {
+            'H' => {
+                let mut fields = body.splitn(3, ' ');
+                let version = fields.next().ok_or_else(malformed)?;
+                Message::Hello {
+                    version: version.parse().map_err(|_| malformed())?,
+                    features: decode_features(fields.next().unwrap_or("-")),
+                    name: fields.next().unwrap_or("").to_string(),
+                }
+            }
+            'C' => Message::Welcome {
+                features: decode_features(body),
+            },
+            'N' => Message::Refused(Refusal::from_code(body).ok_or_else(malformed)?),
+            'K' => Message::Heartbeat,
+            'G' => Message::Ready,
+            'u' => Message::Rules(body.parse().map_err(|_| malformed())?),
+            'n' => Message::Composition(body.parse().map_err(|_| malformed())?),
+            'a' => Message::Teams(decode_pairs(body).ok_or_else(malformed)?),
+            'c' => Message::Scores(decode_pairs(body).ok_or_else(malformed)?),
+            'h' => Message::Commitment(body.to_string()),
+            'E' => Message::Entropy(body.parse().map_err(|_| malformed())?),
+            'd' => Message::DealEntropy(decode_pairs(body).ok_or_else(malformed)?),
+            's' => {
+                let (seats, hand) = body.split_once(';').ok_or_else(malformed)?;
+                Message::Deal {
+                    seats: decode_pairs(seats).ok_or_else(malformed)?,
+                    hand: decode_cards(hand).ok_or_else(malformed)?,
+                }
+            }
+            'z' => {
+                let (count, cards) = body.split_once(' ').unwrap_or((body, ""));
+                Message::Leftover {
+                    count: count.parse().map_err(|_| malformed())?,
+                    cards: decode_cards(cards).ok_or_else(malformed)?,
+                }
+            }
+            't' => {
+                let mut fields = body.splitn(3, ' ');
+                let from = fields.next().ok_or_else(malformed)?;
+                let to = fields.next().ok_or_else(malformed)?;
+                Message::Tribute {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                    cards: decode_cards(fields.next().unwrap_or("")).ok_or_else(malformed)?,
+                }
+            }
+            'g' => {
+                let (count, to) = body.split_once(' ').ok_or_else(malformed)?;
+                Message::AskTribute {
+                    count: count.parse().map_err(|_| malformed())?,
+                    to: to.to_string(),
+                }
+            }
+            'b' => Message::TributeReturn(decode_cards(body).ok_or_else(malformed)?),
+            'l' => {
+                let (name, card) = body.split_once(' ').unwrap_or((body, ""));
+                Message::Leader {
+                    name: name.to_string(),
+                    card: match card {
+                        "" => None,
+                        card => Some(card.parse().map_err(|_| malformed())?),
+                    },
+                }
+            }
+            'v' => {
+                let mut fields = body.split_whitespace();
+                let secret = fields.next().ok_or_else(malformed)?;
+                Message::Reveal {
+                    secret: crate::fair_deal::from_hex(secret).ok_or_else(malformed)?,
+                    order: fields.map(str::to_string).collect(),
+                }
+            }
+            'r' => Message::NewRound,
+            'm' => Message::Prompt {
+                time_left: body.parse().ok().map(Duration::from_millis),
+            },
+            'p' => Message::Play(body.parse().map_err(|_| malformed())?),
+            'P' => Message::Pass(body.to_string()),
+            'x' => Message::Reject(Rejection::from_code(body).ok_or_else(malformed)?),
+            'y' => Message::CardCounts(decode_pairs(body).ok_or_else(malformed)?),
+            'k' => {
+                let clocks: Vec<(String, u64)> = decode_pairs(body).ok_or_else(malformed)?;
+                Message::Clocks(
+                    clocks
+                        .into_iter()
+                        .map(|(name, millis)| (name, Duration::from_millis(millis)))
+                        .collect(),
+                )
+            }
+            'w' => {
+                let (place, name) = body.split_once(' ').ok_or_else(malformed)?;
+                Message::WentOut {
+                    place: place.parse().map_err(|_| malformed())?,
+                    name: name.to_string(),
+                }
+            }
+            'q' => Message::Forfeit(body.to_string()),
+            'e' => Message::RoundEnd,
+            'f' => Message::Standings(body.split_whitespace().map(str::to_string).collect()),
+            'o' => Message::GameEnd {
+                winner: body.to_string(),
+            },
+            _ => return Err(malformed()),
+        };
+        Ok(message)
+    }
+}
+
+// Passes are sent as their own message, naming the player who passed
+impl From<Play> for Message {
+    fn from(play: Play) -> Self {
+        if play.class == Class::Pass {
+            Message::Pass(play.player.name)
+        } else {
+            Message::Play(play)
+        }
+    }
+}
+
+// Lists of pairs are sent as name:value separated by spaces
+fn encode_pairs<T>(pairs: &[(String, T)]) -> String
+where
+    T: fmt::Display,
+{
+    let pairs: Vec<String> = pairs
+        .iter()
+        .map(|(name, value)| format!("{name}:{value}"))
+        .collect();
+    pairs.join(" ")
+}
+
+fn decode_pairs<T>(text: &str) -> Option<Vec<(String, T)>>
+where
+    T: FromStr,
+{
+    text.split_whitespace()
+        .map(|pair| {
+            let (name, value) = pair.rsplit_once(':')?;
+            Some((name.to_string(), value.parse().ok()?))
+        })
+        .collect()
+}
+
+// Features are sent separated by commas, with - for none
+fn encode_features(features: &[String]) -> String {
+    if features.is_empty() {
+        "-".to_string()
+    } else {
+        features.join(",")
+    }
+}
+
+fn decode_features(text: &str) -> Vec<String> {
+    text.split(',')
+        .filter(|feature| !feature.is_empty() && *feature != "-")
+        .map(str::to_string)
+        .collect()
+}
+
+fn encode_cards(cards: &[Card]) -> String {
+    let cards: Vec<String> = cards.iter().cloned().map(Into::into).collect();
+    cards.join(" ")
+}
+
+fn decode_cards(text: &str) -> Option<Vec<Card>> {
+    text.split_whitespace()
+        .map(|card| card.parse().ok())
+        .collect()
+}
+
+// Reasons the server can turn a client away during the handshake
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Refusal {
+    Version(u32), // The client speaks another version of the protocol, the server's is given
+    NameTaken,
+    InvalidName,
+    Malformed,   // The server could not read the hello
+    GameStarted, // The game started before the player was seated
+}
+
+impl Refusal {
+    // Code used for the refusal when sending it over the network
+    pub fn code(&self) -> String {
+        match self {
+            Refusal::Version(version) => format!("version {version}"),
+            Refusal::NameTaken => "name".to_string(),
+            Refusal::InvalidName => "invalid".to_string(),
+            Refusal::Malformed => "malformed".to_string(),
+            Refusal::GameStarted => "started".to_string(),
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Refusal> {
+        match code.split_once(' ') {
+            Some(("version", version)) => version.parse().ok().map(Refusal::Version),
+            _ => match code {
+                "name" => Some(Refusal::NameTaken),
+                "invalid" => Some(Refusal::InvalidName),
+                "malformed" => Some(Refusal::Malformed),
+                "started" => Some(Refusal::GameStarted),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Refusal::Version(version) => write!(
+                f,
+                "The server speaks protocol version {version}, this build speaks version {PROTOCOL_VERSION}. Please use the same version of the game."
+            ),
+            Refusal::NameTaken => write!(f, "That name is already taken."),
+            Refusal::InvalidName => {
+                write!(f, "Names cannot be empty or contain spaces, colons or slashes.")
+            }
+            Refusal::Malformed => write!(f, "The server could not read the handshake."),
+            Refusal::GameStarted => write!(f, "The game has already started."),
+        }
+    }
+}
+
+// Whether a name can be sent in lists separated by spaces and colons
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(|c: char| c == '/' || c == ':' || c.is_whitespace())
+}
+
+pub fn write_message<W>(writer: &mut W, message: &Message) -> io::Result<()>
+where
+    W: Write,
+{
+    let payload = message.encode();
+    let length = u32::try_from(payload.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Message too long"))?;
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&length.to_be_bytes());
+    frame.extend_from_slice(payload.as_bytes());
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+// Reads exactly one frame, so nothing past the end of the message is taken from the stream
+pub fn read_message<R>(reader: &mut R) -> Result<Message, ProtocolError>
+where
+    R: Read,
+{
+    let mut length = [0u8; 4];
+    reader.read_exact(&mut length)?;
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_FRAME_LENGTH {
+        return Err(ProtocolError::TooLong(length));
+    }
+
+    let mut payload = vec![0u8; length];
+    reader.read_exact(&mut payload)?;
+    let text = String::from_utf8(payload).map_err(|_| ProtocolError::InvalidUtf8)?;
+    Message::decode(&text)
+}
+
+// Encoding a connection sends its messages in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Codec {
+    Native,    // Length-prefixed frames of tagged text
+    Json,      // One JSON object per line
+    WebSocket, // One JSON object per WebSocket message
+}
+
+impl Codec {
+    // The encoding a connection switches to once the handshake agreed on its features
+    pub fn from_features(features: &[String]) -> Self {
+        if features.iter().any(|feature| feature == JSON_FEATURE) {
+            Codec::Json
+        } else {
+            Codec::Native
+        }
+    }
+
+    // The encoding a hello starting with this byte was sent in
+    pub fn detect(first_byte: u8) -> Self {
+        if first_byte == b'{' {
+            Codec::Json
+        } else {
+            Codec::Native
+        }
+    }
+
+    pub fn write<W>(&self, writer: &mut W, message: &Message) -> io::Result<()>
+    where
+        W: Write,
+    {
+        match self {
+            Codec::Native => write_message(writer, message),
+            Codec::Json => write_json(writer, message),
+            Codec::WebSocket => {
+                let text = serde_json::to_string(message)?;
+                websocket::write_text(writer, &text)
+            }
+        }
+    }
+
+    pub fn read<R>(&self, reader: &mut R) -> Result<Message, ProtocolError>
+    where
+        R: Read,
+    {
+        self.read_replying(reader, &mut io::sink())
+    }
+
+    // Read a message, writing the answers to any WebSocket pings that come before it to replies
+    pub fn read_replying<R, W>(
+        &self,
+        reader: &mut R,
+        replies: &mut W,
+    ) -> Result<Message, ProtocolError>
+    where
+        R: Read,
+        W: Write,
+    {
+        match self {
+            Codec::Native => read_message(reader),
+            Codec::Json => read_json(reader),
+            Codec::WebSocket => {
+                let text = websocket::read_text(reader, replies)?;
+                serde_json::from_str(&text).map_err(ProtocolError::Json)
+            }
+        }
+    }
+}
+
+pub fn write_json<W>(writer: &mut W, message: &Message) -> io::Result<()>
+where
+    W: Write,
+{
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes())?;
+    writer.flush()
+}
+
+// Reads one byte at a time, so nothing past the end of the line is taken from the stream.
+// Blank lines are skipped.
+pub fn read_json<R>(reader: &mut R) -> Result<Message, ProtocolError>
+where
+    R: Read,
+{
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        reader.read_exact(&mut byte)?;
+        match byte[0] {
+            b'\n' if line.iter().all(u8::is_ascii_whitespace) => line.clear(),
+            b'\n' => break,
+            _ if line.len() >= MAX_FRAME_LENGTH => return Err(ProtocolError::TooLong(line.len())),
+            byte => line.push(byte),
+        }
+    }
+
+    let text = String::from_utf8(line).map_err(|_| ProtocolError::InvalidUtf8)?;
+    serde_json::from_str(&text).map_err(ProtocolError::Json)
+}
+
+// Durations are sent in JSON as whole milliseconds
+mod optional_millis {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match duration {
+            Some(duration) => serializer.serialize_some(&(duration.as_millis() as u64)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let millis: Option<u64> = Option::deserialize(deserializer)?;
+        Ok(millis.map(Duration::from_millis))
+    }
+}
+
+mod clock_millis {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S>(clocks: &[(String, Duration)], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let clocks: Vec<(&str, u64)> = clocks
+            .iter()
+            .map(|(name, clock)| (name.as_str(), clock.as_millis() as u64))
+            .collect();
+        clocks.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<(String, Duration)>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let clocks: Vec<(String, u64)> = Vec::deserialize(deserializer)?;
+        Ok(clocks
+            .into_iter()
+            .map(|(name, millis)| (name, Duration::from_millis(millis)))
+            .collect())
+    }
+}
+
+// The revealed secret is sent in JSON as hex, the same as on the native wire
+mod hex {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&crate::fair_deal::to_hex(bytes))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let hex = String::deserialize(deserializer)?;
+        crate::fair_deal::from_hex(&hex).ok_or_else(|| de::Error::custom("invalid hex"))
+    }
+}
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    Io(io::Error),
+    TooLong(usize),
+    InvalidUtf8,
+    Malformed(String),
+    Json(serde_json::Error),
+}
+
+impl ProtocolError {
+    // Whether the error is only a read timing out, rather than a broken connection
+    pub fn is_timeout(&self) -> bool {
+        matches!(
+            self,
+            ProtocolError::Io(e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+        )
+    }
+
+    // Whether the connection is gone. Anything else was a message the peer can send again.
+    pub fn is_disconnect(&self) -> bool {
+        matches!(self, ProtocolError::Io(_))
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolError::Io(e) => write!(f, "connection error: {e}"),
+            ProtocolError::TooLong(length) => write!(f, "message of {length} bytes is too long"),
+            ProtocolError::InvalidUtf8 => write!(f, "message is not valid UTF-8"),
+            ProtocolError::Malformed(text) => write!(f, "could not read message \"{text}\""),
+            ProtocolError::Json(e) => write!(f, "could not read JSON message: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl From<io::Error> for ProtocolError {
+    fn from(e: io::Error) -> Self {
+        ProtocolError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::{Rank, Suit};
+    use crate::deck::Leftover;
+    use crate::rules::ForfeitPolicy;
+
+    fn round_trip(message: Message) -> Message {
+        let mut frame = Vec::new();
+        write_message(&mut frame, &message).unwrap();
+        read_message(&mut frame.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn messages_survive_framing() {
+        let hand = vec![
+            Card::new(Suit::Hearts, Rank::Three),
+            Card::with_deck(Suit::Red, Rank::Joker, 1),
+        ];
+        match round_trip(Message::Deal {
+            seats: vec![("ann".to_string(), 2), ("bo".to_string(), 2)],
+            hand: hand.clone(),
+        }) {
+            Message::Deal { seats, hand: dealt } => {
+                assert_eq!(seats, vec![("ann".to_string(), 2), ("bo".to_string(), 2)]);
+                assert!(dealt == hand);
+            }
+            _ => panic!("Expected a deal"),
+        }
+
+        match round_trip(Message::Prompt {
+            time_left: Some(Duration::from_millis(1500)),
+        }) {
+            Message::Prompt { time_left } => {
+                assert_eq!(time_left, Some(Duration::from_millis(1500)))
+            }
+            _ => panic!("Expected a prompt"),
+        }
+
+        match round_trip(Message::Reject(Rejection::DoesNotBeat)) {
+            Message::Reject(rejection) => assert_eq!(rejection, Rejection::DoesNotBeat),
+            _ => panic!("Expected a rejection"),
+        }
+    }
+
+    #[test]
+    fn hello_carries_version_and_features() {
+        let hello = Message::Hello {
+            version: PROTOCOL_VERSION,
+            name: "ann".to_string(),
+            features: vec!["a".to_string(), "b".to_string()],
+        };
+        match round_trip(hello) {
+            Message::Hello {
+                version,
+                name,
+                features,
+            } => {
+                assert_eq!(version, PROTOCOL_VERSION);
+                assert_eq!(name, "ann");
+                assert_eq!(features, vec!["a".to_string(), "b".to_string()]);
+            }
+            _ => panic!("Expected a hello"),
+        }
+
+        match round_trip(Message::Refused(Refusal::Version(3))) {
+            Message::Refused(refusal) => assert_eq!(refusal, Refusal::Version(3)),
+            _ => panic!("Expected a refusal"),
+        }
+    }
+
+    #[test]
+    fn json_lines_carry_the_same_messages() {
+        let mut stream = Vec::new();
+        let codec = Codec::Json;
+        codec
+            .write(&mut stream, &Message::Prompt { time_left: None })
+            .unwrap();
+        codec
+            .write(
+                &mut stream,
+                &Message::Clocks(vec![("ann".to_string(), Duration::from_millis(2500))]),
+            )
+            .unwrap();
+        assert!(String::from_utf8(stream.clone())
+            .unwrap()
+            .starts_with("{\"type\":\"prompt\",\"data\":{\"time_left\":null}}\n"));
+
+        // Bots can write plays by hand, leaving out the wildcards
+        stream.extend_from_slice(
+            b"\n{\"type\":\"play\",\"data\":{\"player\":\"ann\",\"cards\":[\"3H\",\"3D\"]}}\n",
+        );
+
+        let mut reader = stream.as_slice();
+        assert!(matches!(
+            codec.read(&mut reader),
+            Ok(Message::Prompt { time_left: None })
+        ));
+        match codec.read(&mut reader) {
+            Ok(Message::Clocks(clocks)) => {
+                assert_eq!(
+                    clocks,
+                    vec![("ann".to_string(), Duration::from_millis(2500))]
+                )
+            }
+            _ => panic!("Expected clocks"),
+        }
+        match codec.read(&mut reader) {
+            Ok(Message::Play(play)) => {
+                assert_eq!(play.player.name, "ann");
+                assert_eq!(play.cards.len(), 2);
+            }
+            _ => panic!("Expected a play"),
+        }
+    }
+
+    fn json_round_trip(message: &Message) -> Message {
+        let mut line = Vec::new();
+        Codec::Json.write(&mut line, message).unwrap();
+        Codec::Json.read(&mut line.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn json_messages_are_structured() {
+        let mut rules = RuleSet::new();
+        rules.jokers_wild = true;
+        rules.forfeit = ForfeitPolicy::Forfeit;
+        let text = serde_json::to_string(&Message::Rules(rules)).unwrap();
+        assert!(text.contains("\"jokers_wild\":true"));
+        assert!(text.contains("\"forfeit\":\"forfeit\""));
+        assert!(matches!(json_round_trip(&Message::Rules(rules)), Message::Rules(r) if r == rules));
+
+        let composition = Composition {
+            num_decks: 2,
+            jokers: 1,
+            removed_ranks: vec![Rank::Three, Rank::Ten],
+            leftover: Leftover::Shown,
+        };
+        let text = serde_json::to_string(&Message::Composition(composition.clone())).unwrap();
+        assert!(text.contains("\"removed_ranks\":[\"3\",\"0\"]"));
+        assert!(matches!(
+            json_round_trip(&Message::Composition(composition.clone())),
+            Message::Composition(c) if c == composition
+        ));
+
+        let play: Play = "ann 5♥ RO=5".parse().unwrap();
+        let text = serde_json::to_string(&Message::Play(play)).unwrap();
+        assert!(
+            text.contains("{\"player\":\"ann\",\"cards\":[\"5♥\",\"RO\"],\"wildcards\":[\"5\"]}")
+        );
+        match json_round_trip(&Message::Play("ann 5♥ RO=5".parse().unwrap())) {
+            Message::Play(play) => {
+                assert_eq!(play.player.name, "ann");
+                assert_eq!(play.wildcards, vec![Rank::Five]);
+                assert_eq!(play.class, Class::Double);
+            }
+            _ => panic!("Expected a play"),
+        }
+
+        let text = serde_json::to_string(&Message::Reject(Rejection::DoesNotBeat)).unwrap();
+        assert_eq!(text, "{\"type\":\"reject\",\"data\":\"does_not_beat\"}");
+
+        // A wildcard needs a joker to stand in for
+        let text = "{\"type\":\"play\",\"data\":{\"player\":\"ann\",\"cards\":[\"5♥\"],\"wildcards\":[\"5\"]}}";
+        assert!(serde_json::from_str::<Message>(text).is_err());
+    }
+
+    #[test]
+    fn garbled_counts_are_refused() {
+        assert!(matches!(
+            Message::decode("g2 bo"),
+            Ok(Message::AskTribute { count: 2, .. })
+        ));
+        assert!(Message::decode("gtwo bo").is_err());
+        assert!(Message::decode("z? 3♥").is_err());
+        assert!(Message::decode("u3 yes").is_err());
+        assert!(Message::decode("ntwo").is_err());
+    }
+
+    #[test]
+    fn frames_are_read_one_at_a_time() {
+        let mut stream = Vec::new();
+        write_message(&mut stream, &Message::Heartbeat).unwrap();
+        write_message(&mut stream, &Message::Pass("ann".to_string())).unwrap();
+        let mut reader = stream.as_slice();
+        assert!(matches!(read_message(&mut reader), Ok(Message::Heartbeat)));
+        assert!(matches!(read_message(&mut reader), Ok(Message::Pass(name)) if name == "ann"));
+        assert!(read_message(&mut reader).is_err());
+    }
+
+    #[test]
+    fn oversized_frames_are_refused() {
+        let mut frame = (MAX_FRAME_LENGTH as u32 + 1).to_be_bytes().to_vec();
+        frame.extend_from_slice(b"r");
+        assert!(matches!(
+            read_message(&mut frame.as_slice()),
+            Err(ProtocolError::TooLong(_))
+        ));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::play;
+use crate::player;
 
 pub struct Round {
     pub plays: Vec<play::Play>, // List of plays in the round
@@ -13,6 +14,30 @@ impl Round {
     pub fn add_play(&mut self, play: play::Play) {
         self.plays.push(play);
     }
+
+    // The last play of the round that was not a pass
+    pub fn last_play(&self) -> Option<&play::Play> {
+        self.plays
+            .iter()
+            .rev()
+            .find(|play| play.class != play::Class::Pass)
+    }
+
+    // The player who made the last play that was not a pass
+    pub fn winner(&self) -> Option<&player::Player> {
+        self.last_play().map(|play| &play.player)
+    }
+
+    // The round is over once everyone else has passed since the last play
+    pub fn is_over(&self, num_players: usize) -> bool {
+        let passes = self
+            .plays
+            .iter()
+            .rev()
+            .take_while(|play| play.class == play::Class::Pass)
+            .count();
+        self.last_play().is_some() && passes + 1 >= num_players
+    }
 }
 
 impl AsMut<Round> for Round {
@@ -20,3 +45,52 @@ impl AsMut<Round> for Round {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::{Card, Rank, Suit};
+    use crate::player::Player;
+
+    fn single(name: &str, rank: Rank) -> play::Play {
+        let mut play = play::Play::new(Player::new(name));
+        play.set_cards(vec![Card::new(Suit::Spades, rank)]);
+        play
+    }
+
+    #[test]
+    fn round_is_over_once_everyone_else_passed() {
+        let mut round = Round::new();
+        assert!(!round.is_over(4));
+
+        round.add_play(single("ann", Rank::Five));
+        round.add_play(play::Play::pass(Player::new("bo")));
+        round.add_play(single("cy", Rank::Nine));
+        round.add_play(play::Play::pass(Player::new("dee")));
+        round.add_play(play::Play::pass(Player::new("ann")));
+        assert!(!round.is_over(4));
+
+        round.add_play(play::Play::pass(Player::new("bo")));
+        assert!(round.is_over(4));
+        assert_eq!(
+            round.winner().map(|winner| winner.name.as_str()),
+            Some("cy")
+        );
+    }
+
+    #[test]
+    fn round_is_over_when_one_player_is_left() {
+        // ann goes out on a two, and only bo is left to answer it
+        let mut round = Round::new();
+        round.add_play(single("ann", Rank::Two));
+        assert!(!round.is_over(2));
+
+        round.add_play(play::Play::pass(Player::new("bo")));
+        assert!(round.is_over(2));
+
+        // Passing alone never ends a round
+        let mut round = Round::new();
+        round.add_play(play::Play::pass(Player::new("bo")));
+        assert!(!round.is_over(1));
+    }
+}
@@ -0,0 +1,293 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+// House rules picked by the host and shared with every client. Fields missing from JSON keep
+// their default.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RuleSet {
+    pub min_straight_length: usize, // Fewest cards a straight can be made of
+    pub twos_in_straights: bool,
+    pub jokers_in_straights: bool,
+    pub matching_length: bool, // Plays must have as many cards as the play they beat
+    pub bombs: bool,           // Whether four or more of a kind beat every other class
+    pub jokers_wild: bool,     // Whether jokers can stand in for any rank
+    pub suit_tie_break: bool,  // Whether plays of the same rank are compared by suit
+    pub tribute: usize,        // Cards the last player gives the winner between hands, 0 to 2
+    pub turn_time: u64,        // Seconds a player has to make a move, 0 for no limit
+    pub time_bank: u64,        // Seconds each player has for the whole hand, 0 to use turn_time
+    pub increment: u64,        // Seconds added to the time bank after every move
+    pub forfeit: ForfeitPolicy,
+    pub teams: bool, // Partnerships of two across the table, with four players
+    pub partner_ends_obligation: bool, // Pass automatically while your partner holds the lead
+    pub opening_card: OpeningCard, // Card that decides who leads a hand
+    pub winner_leads: bool, // In match play, the last hand's winner leads instead
+    pub must_play_opening_card: bool, // The first play of the hand must include the opening card
+}
+
+// Card whose holder leads the first round of a hand
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OpeningCard {
+    ThreeOfHearts, // Falls back to the lowest card if the 3♥ was not dealt
+    Lowest,        // Lowest card dealt, breaking ties by suit
+}
+
+impl RuleSet {
+    pub fn new() -> Self {
+        Self {
+            min_straight_length: 5,
+            twos_in_straights: true,
+            jokers_in_straights: true,
+            matching_length: false,
+            bombs: true,
+            jokers_wild: false,
+            suit_tie_break: false,
+            tribute: 1,
+            turn_time: 0,
+            time_bank: 0,
+            increment: 0,
+            forfeit: ForfeitPolicy::AutoMove,
+            teams: false,
+            partner_ends_obligation: false,
+            opening_card: OpeningCard::ThreeOfHearts,
+            winner_leads: false,
+            must_play_opening_card: false,
+        }
+    }
+}
+
+// How many hands a match lasts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchGoal {
+    TargetScore(u16), // Play until somebody reaches the score
+    Hands(u16),       // Play a fixed number of hands
+}
+
+// Settings for a match of several hands
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchRules {
+    pub points: Vec<u16>, // Points for each finishing place, from first place down
+    pub goal: MatchGoal,
+    pub sweep_bonus: u16, // Extra points for a team whose players finish first and second
+}
+
+impl MatchRules {
+    pub fn new() -> Self {
+        Self {
+            points: vec![3, 2, 1],
+            goal: MatchGoal::TargetScore(10),
+            sweep_bonus: 3,
+        }
+    }
+
+    // Points for finishing in a place, counting from 0. Places past the table score nothing.
+    pub fn points_for(&self, place: usize) -> u16 {
+        self.points.get(place).copied().unwrap_or(0)
+    }
+
+    // Whether the match can end. Playing to a score needs first place to score points, since
+    // somebody finishes first in every hand.
+    pub fn check(&self) -> Result<(), String> {
+        match self.goal {
+            MatchGoal::TargetScore(target) if target > 0 && self.points_for(0) == 0 => Err(
+                "First place has to score points, or nobody can reach the target score."
+                    .to_string(),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn is_over(&self, hands_played: u16, top_score: u16) -> bool {
+        match self.goal {
+            MatchGoal::TargetScore(target) => top_score >= target,
+            MatchGoal::Hands(hands) => hands_played >= hands,
+        }
+    }
+}
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for MatchRules {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let points: Vec<String> = self.points.iter().map(|p| p.to_string()).collect();
+        let points = points.join(", ");
+        match self.goal {
+            MatchGoal::TargetScore(target) => {
+                write!(f, "Playing to {target} points, places score {points}")
+            }
+            MatchGoal::Hands(hands) => write!(f, "Playing {hands} hands, places score {points}"),
+        }
+    }
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for RuleSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Straights of {}+ cards, 2s in straights: {}, jokers in straights: {}, matching length: {}, bombs: {}, jokers wild: {}, suit tie break: {}, tribute: {}, turn time: {}s, time bank: {}s + {}s, out of time: {:?}, teams: {}, partner ends obligation: {}, opening card: {:?}, winner leads: {}, must play opening card: {}",
+            self.min_straight_length,
+            self.twos_in_straights,
+            self.jokers_in_straights,
+            self.matching_length,
+            self.bombs,
+            self.jokers_wild,
+            self.suit_tie_break,
+            self.tribute,
+            self.turn_time,
+            self.time_bank,
+            self.increment,
+            self.forfeit,
+            self.teams,
+            self.partner_ends_obligation,
+            self.opening_card,
+            self.winner_leads,
+            self.must_play_opening_card
+        )
+    }
+}
+
+impl From<RuleSet> for String {
+    fn from(rules: RuleSet) -> Self {
+        let flag = |value: bool| if value { "1" } else { "0" };
+        format!(
+            "{} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {}",
+            rules.min_straight_length,
+            flag(rules.twos_in_straights),
+            flag(rules.jokers_in_straights),
+            flag(rules.matching_length),
+            flag(rules.bombs),
+            flag(rules.jokers_wild),
+            flag(rules.suit_tie_break),
+            rules.tribute,
+            rules.turn_time,
+            rules.time_bank,
+            rules.increment,
+            flag(rules.forfeit == ForfeitPolicy::Forfeit),
+            flag(rules.teams),
+            flag(rules.partner_ends_obligation),
+            flag(rules.opening_card == OpeningCard::Lowest),
+            flag(rules.winner_leads),
+            flag(rules.must_play_opening_card)
+        )
+    }
+}
+
+impl FromStr for RuleSet {
+    type Err = ParseRulesError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        // Fall back to the default rules for anything missing, but refuse anything unreadable
+        let mut rules = RuleSet::new();
+        let mut value = value.split_whitespace();
+        fn number<T: FromStr>(field: Option<&str>, default: T) -> Result<T, ParseRulesError> {
+            field.map_or(Ok(default), |s| {
+                s.parse().map_err(|_| ParseRulesError(s.to_string()))
+            })
+        }
+        fn flag(field: Option<&str>, default: bool) -> Result<bool, ParseRulesError> {
+            match field {
+                None => Ok(default),
+                Some("0") => Ok(false),
+                Some("1") => Ok(true),
+                Some(s) => Err(ParseRulesError(s.to_string())),
+            }
+        }
+        rules.min_straight_length = number(value.next(), rules.min_straight_length)?;
+        rules.twos_in_straights = flag(value.next(), rules.twos_in_straights)?;
+        rules.jokers_in_straights = flag(value.next(), rules.jokers_in_straights)?;
+        rules.matching_length = flag(value.next(), rules.matching_length)?;
+        rules.bombs = flag(value.next(), rules.bombs)?;
+        rules.jokers_wild = flag(value.next(), rules.jokers_wild)?;
+        rules.suit_tie_break = flag(value.next(), rules.suit_tie_break)?;
+        rules.tribute = number(value.next(), rules.tribute)?;
+        rules.turn_time = number(value.next(), rules.turn_time)?;
+        rules.time_bank = number(value.next(), rules.time_bank)?;
+        rules.increment = number(value.next(), rules.increment)?;
+        if flag(value.next(), false)? {
+            rules.forfeit = ForfeitPolicy::Forfeit;
+        }
+        rules.teams = flag(value.next(), false)?;
+        rules.partner_ends_obligation = flag(value.next(), false)?;
+        if flag(value.next(), false)? {
+            rules.opening_card = OpeningCard::Lowest;
+        }
+        rules.winner_leads = flag(value.next(), false)?;
+        rules.must_play_opening_card = flag(value.next(), false)?;
+        match value.next() {
+            Some(extra) => Err(ParseRulesError(extra.to_string())),
+            None => Ok(rules),
+        }
+    }
+}
+
+// A field of the rules that could not be read
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseRulesError(pub String);
+
+impl fmt::Display for ParseRulesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unreadable rule: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseRulesError {}
+
+// What happens to a player whose time bank runs out
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ForfeitPolicy {
+    AutoMove, // Every move is made for them for the rest of the hand
+    Forfeit,  // They give up the hand and take last place
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_score_needs_points_for_first_place() {
+        let scoreless = MatchRules {
+            points: vec![0, 0, 0],
+            ..MatchRules::new()
+        };
+        assert!(scoreless.check().is_err());
+        assert!(MatchRules {
+            goal: MatchGoal::Hands(3),
+            ..scoreless.clone()
+        }
+        .check()
+        .is_ok());
+        assert!(MatchRules::new().check().is_ok());
+    }
+
+    #[test]
+    fn rules_are_read_back_or_refused() {
+        let rules = RuleSet {
+            min_straight_length: 5,
+            jokers_wild: true,
+            forfeit: ForfeitPolicy::Forfeit,
+            winner_leads: true,
+            ..RuleSet::new()
+        };
+        let rules_str: String = rules.into();
+        assert_eq!(rules_str.parse(), Ok(rules));
+
+        // Missing fields keep their default
+        assert_eq!("".parse(), Ok(RuleSet::new()));
+        assert!("five".parse::<RuleSet>().is_err());
+        assert!("3 yes".parse::<RuleSet>().is_err());
+        assert!(format!("{rules_str} 1").parse::<RuleSet>().is_err());
+    }
+}
@@ -1,18 +1,112 @@
 use std::collections::VecDeque;
-use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::net::{TcpListener, TcpStream};
-use std::str::{from_utf8, Bytes};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
 use crate::display::Renderable;
 use crate::logger::Logger;
-use crate::play::Play;
 use crate::player::{self, Player};
+use crate::protocol::{self, Codec, Message, ProtocolError, Refusal, FEATURES, PROTOCOL_VERSION};
+use crate::websocket::{self, Request};
+
+// A player's connection, and the encoding agreed on for it during the handshake. Bytes are
+// kept until a whole message has arrived, so a read that times out halfway through a message
+// picks up where it left off.
+pub struct Connection {
+    pub stream: TcpStream,
+    pub codec: Codec,
+    received: Vec<u8>, // Bytes read from the stream that do not make up a whole message yet
+    skipping: usize,   // Bytes of a refused frame that are still to be thrown away
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream, codec: Codec) -> Self {
+        Self {
+            stream,
+            codec,
+            received: Vec::new(),
+            skipping: 0,
+        }
+    }
+
+    pub fn send(&self, message: &Message) -> Result<(), std::io::Error> {
+        self.codec.write(&mut &self.stream, message)
+    }
+
+    // Read the next message, waiting as long as the stream's read timeout allows
+    pub fn read(&mut self) -> Result<Message, ProtocolError> {
+        loop {
+            if let Some(message) = self.take_message()? {
+                return Ok(message);
+            }
+            self.receive()?;
+        }
+    }
+
+    // Read a message that has already arrived, without blocking
+    pub fn try_read(&mut self) -> Result<Option<Message>, ProtocolError> {
+        if let Some(message) = self.take_message()? {
+            return Ok(Some(message));
+        }
+        self.stream.set_nonblocking(true)?;
+        let received = self.receive();
+        self.stream.set_nonblocking(false)?;
+        match received {
+            Ok(()) => self.take_message(),
+            Err(e) if e.is_timeout() => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Take the first message out of the bytes received so far, if all of it has arrived
+    fn take_message(&mut self) -> Result<Option<Message>, ProtocolError> {
+        let skipped = self.skipping.min(self.received.len());
+        self.received.drain(..skipped);
+        self.skipping -= skipped;
+        if self.skipping > 0 {
+            return Ok(None);
+        }
+
+        let mut unread = self.received.as_slice();
+        let mut replies = Vec::new();
+        let message = self.codec.read_replying(&mut unread, &mut replies);
+        let consumed = self.received.len() - unread.len();
+        match message {
+            // The pings are answered once the whole message has arrived
+            Err(ProtocolError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            message => {
+                self.received.drain(..consumed);
+                // A pong that cannot be sent shows up as a broken stream on the next read
+                (&self.stream).write_all(&replies).ok();
+                // Only the header of a frame that is too long has been read, so the rest of it
+                // is thrown away as it arrives to find the start of the next message
+                if let (Codec::Native, Err(ProtocolError::TooLong(length))) = (self.codec, &message)
+                {
+                    self.skipping = *length;
+                }
+                message.map(Some)
+            }
+        }
+    }
+
+    fn receive(&mut self) -> Result<(), ProtocolError> {
+        let mut chunk = [0u8; 4096];
+        let length = (&self.stream).read(&mut chunk)?;
+        if length == 0 {
+            return Err(ProtocolError::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Connection closed",
+            )));
+        }
+        self.received.extend_from_slice(&chunk[..length]);
+        Ok(())
+    }
+}
 
 pub struct Server {
-    pub player_network: Arc<Mutex<VecDeque<(player::Player, TcpStream, Instant)>>>,
+    pub player_network: Arc<Mutex<VecDeque<(player::Player, Connection, Instant)>>>,
     pub listener_thread: Option<thread::JoinHandle<()>>,
     pub running: Arc<Mutex<bool>>,
     pub logger: Arc<Mutex<dyn Renderable>>,
@@ -29,10 +123,18 @@ impl Server {
     }
 
     //TODO implement player maximum and such
-    pub fn accept_players(&mut self) {
+    // Accept players on the usual port, and from browsers too if a web listener is given
+    pub fn accept_players(&mut self, web_listener: Option<TcpListener>) {
         // Create listener to listen for any new connections
         let listener = TcpListener::bind("127.0.0.1:9141").unwrap();
+        self.accept_players_on(listener, web_listener);
+    }
+
+    pub fn accept_players_on(&mut self, listener: TcpListener, web_listener: Option<TcpListener>) {
         listener.set_nonblocking(true).unwrap();
+        if let Some(web_listener) = &web_listener {
+            web_listener.set_nonblocking(true).unwrap();
+        }
 
         let players_streams = Arc::clone(&self.player_network);
         let running = Arc::clone(&self.running);
@@ -41,52 +143,45 @@ impl Server {
         // Concurrently run thread in order to receive connections
         self.listener_thread = Some(thread::spawn(move || {
             while *running.lock().unwrap() {
-                let mut players = players_streams.lock().unwrap();
-                let mut logger = logger.lock().unwrap();
-
-                match listener.accept() {
-                    Ok((mut stream, addr)) => {
-                        // println!("incoming connection from {}", addr);
-                        logger.as_any().downcast_mut::<Logger>().unwrap().log(
-                            format!("Incoming connection from {addr}"),
-                            Duration::new(0, 0),
-                        );
-                        if let Some(user_name) = validate_player(&stream) {
-                            if players.iter().any(|p| p.0.name == user_name) {
-                                logger.as_any().downcast_mut::<Logger>().unwrap().log(
-                                    format!("Player attempted with duplicate name {user_name}"),
-                                    Duration::new(0, 0),
-                                );
-                                // println!("Player attempted with duplicate name {}", user_name);
-                                stream.write(b"err:name");
-                                stream.shutdown(std::net::Shutdown::Both);
-                                continue;
-                            }
-                            stream.write(b"connected");
-
-                            logger.as_any().downcast_mut::<Logger>().unwrap().log(
-                                format!("Player {user_name} connected from {addr}"),
-                                Duration::new(0, 0),
-                            );
-                            // println!("Player connected");
-                            let player = player::Player::new(user_name);
-                            players.push_back((player, stream, Instant::now()));
-                        }
+                // Connections from the terminal client and bots, then from browsers
+                let mut incoming = Vec::new();
+                let web_accept = web_listener
+                    .as_ref()
+                    .map(|web_listener| web_listener.accept());
+                for (accepted, from_web) in [(listener.accept(), false)]
+                    .into_iter()
+                    .chain(web_accept.map(|accepted| (accepted, true)))
+                {
+                    match accepted {
+                        Ok((stream, addr)) => incoming.push((stream, addr, from_web)),
+                        Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                        Err(e) => eprint!("Error accepting connection: {}", e),
                     }
-                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
-                    Err(e) => eprint!("Error accepting connection: {}", e),
                 }
 
-                for player in players.iter_mut() {
-                    let mut buf_reader = BufReader::new(&player.1);
+                // Every handshake runs on its own thread, so a client that is slow to send its
+                // hello holds up nobody else
+                for (stream, addr, from_web) in incoming {
+                    let players_streams = Arc::clone(&players_streams);
+                    let running = Arc::clone(&running);
+                    let logger = Arc::clone(&logger);
+                    thread::spawn(move || {
+                        handshake(stream, addr, from_web, &players_streams, &running, &logger)
+                    });
+                }
 
-                    let mut data = Vec::new();
-                    if let Some(_) = buf_reader.read_until(b'\0', &mut data).ok() {
-                        match std::str::from_utf8(&data) {
-                            Ok("ok\0") => {
+                let mut players = players_streams.lock().unwrap();
+                let mut logger = logger.lock().unwrap();
+
+                for player in players.iter_mut() {
+                    // Only read what players have already sent, so nobody holds up the lobby
+                    while let Ok(Some(message)) = player.1.try_read() {
+                        match message {
+                            Message::Heartbeat => {
                                 player.2 = Instant::now(); //Update last connected time
                             }
-                            Ok("go\0") => {
+                            Message::Ready => {
+                                player.2 = Instant::now();
                                 *running.lock().unwrap() = false;
                             }
                             _ => {}
@@ -95,14 +190,14 @@ impl Server {
                 }
 
                 // Remove inactive players
-                players.retain(|(player, stream, last_active)| {
+                players.retain(|(player, connection, last_active)| {
                     if last_active.elapsed().as_secs() > 20 {
                         let user_name = &player.name;
                         logger.as_any().downcast_mut::<Logger>().unwrap().log(
                             format!("Removing player {user_name} due to inactivity."),
                             Duration::new(0, 0),
                         );
-                        stream.shutdown(std::net::Shutdown::Both).ok(); // Gracefully close the connection
+                        connection.stream.shutdown(std::net::Shutdown::Both).ok(); // Gracefully close the connection
                         false // Remove this player
                     } else {
                         true // Keep this player
@@ -113,13 +208,12 @@ impl Server {
     }
     pub fn send_all<T>(&mut self, message: T) -> Result<(), std::io::Error>
     where
-        T: Into<String>,
+        T: Into<Message>,
     {
-        let message: String = message.into();
+        let message: Message = message.into();
         let players_streams = self.player_network.lock().unwrap();
-        for (_, stream, _) in players_streams.iter() {
-            let mut writer = BufWriter::new(stream);
-            if let Err(e) = writeln!(writer, "{}", message) {
+        for (_, connection, _) in players_streams.iter() {
+            if let Err(e) = connection.send(&message) {
                 eprintln!("Failed to send message: {}", e);
             }
         }
@@ -128,19 +222,17 @@ impl Server {
 
     pub fn send<T, U>(&mut self, message: T, target_player: U) -> Result<(), std::io::Error>
     where
-        T: Into<String>,
+        T: Into<Message>,
         U: AsRef<Player>,
     {
-        let message: String = message.into();
+        let message: Message = message.into();
         let players_streams = self.player_network.lock().unwrap();
 
-        if let Some((_, stream, _)) = players_streams
+        if let Some((_, connection, _)) = players_streams
             .iter()
             .find(|(player, _, _)| player == target_player.as_ref())
         {
-            let mut writer = BufWriter::new(stream);
-            writeln!(writer, "{}", message)?;
-            Ok(())
+            connection.send(&message)
         } else {
             Err(std::io::Error::new(
                 std::io::ErrorKind::NotFound,
@@ -149,39 +241,440 @@ impl Server {
         }
     }
 
-    pub fn read<T>(&mut self, target_player: T) -> Result<String, std::io::Error>
+    // Read the next message from a player, skipping heartbeats
+    pub fn read<T>(&mut self, target_player: T) -> Result<Message, ProtocolError>
     where
         T: AsRef<Player>,
     {
-        let players_streams = self.player_network.lock().unwrap();
-        if let Some((_, stream, _)) = players_streams
-            .iter()
-            .find(|(player, _, _)| player == target_player.as_ref())
-        {
-            let mut buf_reader = BufReader::new(stream);
+        let mut players_streams = self.player_network.lock().unwrap();
+        let connection = find_connection(&mut players_streams, target_player.as_ref())?;
+        loop {
+            match connection.read()? {
+                Message::Heartbeat => continue,
+                message => return Ok(message),
+            }
+        }
+    }
 
-            let mut data = Vec::new();
-            buf_reader.read_until(b'\0', &mut data)?;
-            Ok(from_utf8(&data).unwrap().to_string())
-        } else {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "Player not found",
-            ))
+    // Read the next message from a player, skipping heartbeats. Returns None if nothing
+    // arrives before the deadline. Part of a message that arrived in time is kept for the
+    // next read.
+    pub fn read_before<T>(
+        &mut self,
+        target_player: T,
+        deadline: Instant,
+    ) -> Result<Option<Message>, ProtocolError>
+    where
+        T: AsRef<Player>,
+    {
+        let mut players_streams = self.player_network.lock().unwrap();
+        let connection = find_connection(&mut players_streams, target_player.as_ref())?;
+        let message = loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break None;
+            }
+            connection.stream.set_read_timeout(Some(remaining))?;
+
+            match connection.read() {
+                Ok(Message::Heartbeat) => {}
+                Ok(message) => break Some(message),
+                Err(e) if e.is_timeout() => break None,
+                Err(e) => {
+                    connection.stream.set_read_timeout(None)?;
+                    return Err(e);
+                }
+            }
+        };
+        connection.stream.set_read_timeout(None)?;
+        Ok(message)
+    }
+
+    // Throw away every whole message a player has already sent. Anything sent before a prompt
+    // answers an earlier one, e.g. a move that arrived after its deadline. Returns how many
+    // messages besides heartbeats were thrown away.
+    pub fn discard_pending<T>(&mut self, target_player: T) -> Result<usize, ProtocolError>
+    where
+        T: AsRef<Player>,
+    {
+        let mut players_streams = self.player_network.lock().unwrap();
+        let connection = find_connection(&mut players_streams, target_player.as_ref())?;
+        let mut discarded = 0;
+        while let Some(message) = connection.try_read()? {
+            if !matches!(message, Message::Heartbeat) {
+                discarded += 1;
+            }
         }
+        Ok(discarded)
     }
 }
 
-fn validate_player(stream: &TcpStream) -> Option<String> {
-    let mut buf_reader = BufReader::new(stream);
+fn find_connection<'a>(
+    players_streams: &'a mut VecDeque<(Player, Connection, Instant)>,
+    target_player: &Player,
+) -> Result<&'a mut Connection, ProtocolError> {
+    players_streams
+        .iter_mut()
+        .find(|(player, _, _)| player == target_player)
+        .map(|(_, connection, _)| connection)
+        .ok_or_else(|| {
+            ProtocolError::Io(io::Error::new(io::ErrorKind::NotFound, "Player not found"))
+        })
+}
 
-    let mut data = Vec::new();
-    buf_reader.read_until(b'\0', &mut data).ok()?;
+// The encoding a client sent its hello in, told apart by the first byte
+fn hello_codec(stream: &TcpStream) -> Codec {
+    stream.set_read_timeout(Some(Duration::from_secs(20))).ok();
+    let mut first_byte = [0u8; 1];
+    let codec = match stream.peek(&mut first_byte) {
+        Ok(1) => Codec::detect(first_byte[0]),
+        _ => Codec::Native,
+    };
+    stream.set_read_timeout(None).ok();
+    codec
+}
+
+// Upgrade a connection from a browser if needed, then read the client's hello and seat them
+fn handshake(
+    mut stream: TcpStream,
+    addr: SocketAddr,
+    from_web: bool,
+    players: &Mutex<VecDeque<(Player, Connection, Instant)>>,
+    running: &Mutex<bool>,
+    logger: &Mutex<dyn Renderable>,
+) {
+    let log = |text: String| {
+        logger
+            .lock()
+            .unwrap()
+            .as_any()
+            .downcast_mut::<Logger>()
+            .unwrap()
+            .log(text, Duration::new(0, 0));
+    };
 
-    let data = String::from_utf8(data).expect("Invalid name");
+    // Accepted streams block, even though the listener does not
+    stream.set_nonblocking(false).ok();
 
-    let index = data.find("name:")?;
-    let username = data.split_at(index + 5).1.trim().to_string();
+    let hello_codec = if from_web {
+        // Browsers load the page first, then upgrade to a WebSocket to join
+        stream.set_read_timeout(Some(Duration::from_secs(5))).ok();
+        let request = websocket::accept(&mut stream);
+        stream.set_read_timeout(None).ok();
+        match request {
+            Ok(Request::WebSocket) => Codec::WebSocket,
+            Ok(Request::Served) => return,
+            Ok(Request::Forbidden(origin)) => {
+                log(format!("Refused WebSocket from {addr} opened by {origin}"));
+                return;
+            }
+            Err(e) => {
+                log(format!("Could not read web request from {addr}: {e}"));
+                return;
+            }
+        }
+    } else {
+        hello_codec(&stream)
+    };
+
+    log(format!("Incoming connection from {addr}"));
+    match join(players, running, stream, hello_codec) {
+        Ok((user_name, codec)) => log(format!(
+            "Player {user_name} connected from {addr} ({codec:?})"
+        )),
+        Err(refusal) => log(format!("Refused connection from {addr}: {refusal}")),
+    }
+}
+
+// Read a client's hello and answer it in the encoding it was sent in. The table is only locked
+// to check the name and seat the player. Returns the name of the player and the encoding agreed
+// on once they are seated.
+fn join(
+    players: &Mutex<VecDeque<(Player, Connection, Instant)>>,
+    running: &Mutex<bool>,
+    mut stream: TcpStream,
+    hello_codec: Codec,
+) -> Result<(String, Codec), Refusal> {
+    let refusal = match validate_player(&stream, hello_codec) {
+        Err(refusal) => refusal,
+        Ok((user_name, features)) => {
+            let mut players = players.lock().unwrap();
+            if !*running.lock().unwrap() {
+                Refusal::GameStarted
+            } else if players.iter().any(|p| p.0.name == user_name) {
+                Refusal::NameTaken
+            } else {
+                // Browsers keep to WebSocket messages whatever features they ask for
+                let codec = match hello_codec {
+                    Codec::WebSocket => Codec::WebSocket,
+                    _ => Codec::from_features(&features),
+                };
+                // Welcome the player before seating them, so it comes before any game
+                // message. A client that does not take it in time is not seated.
+                let welcomed = stream
+                    .set_write_timeout(Some(Duration::from_secs(5)))
+                    .and_then(|()| hello_codec.write(&mut stream, &Message::Welcome { features }))
+                    .and_then(|()| stream.set_write_timeout(None));
+                if welcomed.is_err() {
+                    return Err(Refusal::Malformed);
+                }
+                let player = player::Player::new(user_name.clone());
+                players.push_back((player, Connection::new(stream, codec), Instant::now()));
+                return Ok((user_name, codec));
+            }
+        }
+    };
 
-    Some(username)
+    hello_codec
+        .write(&mut stream, &Message::Refused(refusal.clone()))
+        .ok();
+    stream.shutdown(std::net::Shutdown::Both).ok();
+    Err(refusal)
+}
+
+// Read the hello a player joins with: the name, and the optional features both sides support
+fn validate_player(stream: &TcpStream, codec: Codec) -> Result<(String, Vec<String>), Refusal> {
+    stream
+        .set_read_timeout(Some(Duration::from_secs(20)))
+        .map_err(|_| Refusal::Malformed)?;
+    let hello = codec.read_replying(&mut &*stream, &mut &*stream);
+    stream
+        .set_read_timeout(None)
+        .map_err(|_| Refusal::Malformed)?;
+
+    match hello {
+        Ok(Message::Hello { version, .. }) if version != PROTOCOL_VERSION => {
+            Err(Refusal::Version(PROTOCOL_VERSION))
+        }
+        Ok(Message::Hello { name, features, .. }) => {
+            if !protocol::is_valid_name(&name) {
+                return Err(Refusal::InvalidName);
+            }
+            let features = features
+                .into_iter()
+                .filter(|feature| FEATURES.contains(&feature.as_str()))
+                .collect();
+            Ok((name, features))
+        }
+        _ => Err(Refusal::Malformed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deck::{Composition, Deck};
+    use crate::game_server::GameServer;
+    use crate::hand::Hand;
+    use crate::play::{self, Play};
+    use crate::rules::{MatchGoal, MatchRules, RuleSet};
+    use std::io::{Read, Write};
+    use tungstenite::stream::MaybeTlsStream;
+    use tungstenite::WebSocket;
+
+    type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+    fn send(socket: &mut Socket, message: &Message) {
+        let text = serde_json::to_string(message).unwrap();
+        socket.send(tungstenite::Message::Text(text)).unwrap();
+    }
+
+    fn receive(socket: &mut Socket) -> Message {
+        loop {
+            if let tungstenite::Message::Text(text) = socket.read().unwrap() {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    fn join(address: std::net::SocketAddr, name: &str) -> (Socket, Message) {
+        let (mut socket, _) = tungstenite::connect(format!("ws://{address}/")).unwrap();
+        // Fail the test rather than hang if the server stops answering
+        if let MaybeTlsStream::Plain(stream) = socket.get_ref() {
+            stream
+                .set_read_timeout(Some(Duration::from_secs(10)))
+                .unwrap();
+        }
+        let hello = Message::Hello {
+            version: PROTOCOL_VERSION,
+            name: name.to_string(),
+            features: Vec::new(),
+        };
+        send(&mut socket, &hello);
+        let answer = receive(&mut socket);
+        (socket, answer)
+    }
+
+    // Play the first legal play whenever prompted, until the match is over. Returns the
+    // standings of the hand.
+    fn play_as_bot(mut socket: Socket, name: &str) -> Vec<String> {
+        let mut rules = RuleSet::new();
+        let mut hand = Hand::new();
+        let mut last_play: Option<Play> = None;
+        let mut standings = Vec::new();
+        loop {
+            match receive(&mut socket) {
+                Message::Rules(house_rules) => rules = house_rules,
+                Message::Commitment(_) => send(&mut socket, &Message::Entropy(rand::random())),
+                Message::Deal { hand: cards, .. } => hand = Hand { cards },
+                Message::NewRound => last_play = None,
+                Message::Play(play) => {
+                    if play.player.name == name {
+                        hand.remove_cards(&play.cards);
+                    }
+                    last_play = Some(play);
+                }
+                Message::Prompt { .. } => {
+                    let plays = play::legal_plays(&hand, last_play.as_ref(), &rules);
+                    match plays.first() {
+                        Some(cards) => {
+                            let mut play = Play::new(Player::new(name));
+                            play.set_cards(cards.clone());
+                            send(&mut socket, &Message::Play(play));
+                        }
+                        None => send(&mut socket, &Message::Pass(name.to_string())),
+                    }
+                }
+                Message::Reject(rejection) => panic!("{name}'s move was rejected: {rejection}"),
+                Message::Standings(names) => standings = names,
+                Message::GameEnd { .. } => return standings,
+                _ => {}
+            }
+        }
+    }
+
+    // A server with one player connected over localhost, and the client end of the connection
+    fn seated(name: &str) -> (Server, Player, TcpStream) {
+        let server = Server::new(Arc::new(Mutex::new(Logger::new())));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let player = Player::new(name);
+        server.player_network.lock().unwrap().push_back((
+            player.clone(),
+            Connection::new(stream, Codec::Native),
+            Instant::now(),
+        ));
+        (server, player, client)
+    }
+
+    #[test]
+    fn moves_survive_timeouts_and_late_moves_are_dropped() {
+        let (mut server, player, mut client) = seated("ann");
+        let mut frame = Vec::new();
+        protocol::write_message(&mut frame, &Message::Pass("ann".to_string())).unwrap();
+
+        // Half a move arrives before the deadline, and the rest after it
+        client.write_all(&frame[..3]).unwrap();
+        let deadline = Instant::now() + Duration::from_millis(100);
+        assert!(server.read_before(&player, deadline).unwrap().is_none());
+        client.write_all(&frame[3..]).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        assert!(matches!(
+            server.read_before(&player, deadline),
+            Ok(Some(Message::Pass(name))) if name == "ann"
+        ));
+
+        // A move sent before the next prompt is not taken as the answer to it
+        client.write_all(&frame).unwrap();
+        protocol::write_message(&mut client, &Message::Heartbeat).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(server.discard_pending(&player).unwrap(), 1);
+        let deadline = Instant::now() + Duration::from_millis(100);
+        assert!(server.read_before(&player, deadline).unwrap().is_none());
+    }
+
+    #[test]
+    fn frames_after_a_refused_one_are_read() {
+        let (mut server, player, mut client) = seated("ann");
+
+        // A frame that is too long, sent in two parts, then a move
+        let length = 100 * 1024;
+        client.write_all(&(length as u32).to_be_bytes()).unwrap();
+        client.write_all(&vec![b'p'; length / 2]).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        assert!(matches!(
+            server.read_before(&player, deadline),
+            Err(ProtocolError::TooLong(too_long)) if too_long == length
+        ));
+        client.write_all(&vec![b'p'; length - length / 2]).unwrap();
+        protocol::write_message(&mut client, &Message::Pass("ann".to_string())).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        assert!(matches!(
+            server.read_before(&player, deadline),
+            Ok(Some(Message::Pass(name))) if name == "ann"
+        ));
+    }
+
+    #[test]
+    fn full_hand_over_websocket() {
+        let logger = Arc::new(Mutex::new(Logger::new()));
+        let mut server = Server::new(logger.clone());
+        let web_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let web_address = web_listener.local_addr().unwrap();
+        server.accept_players_on(
+            TcpListener::bind("127.0.0.1:0").unwrap(),
+            Some(web_listener),
+        );
+
+        // The page browsers join from is served on the same port
+        let mut page_stream = TcpStream::connect(web_address).unwrap();
+        page_stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut page = String::new();
+        page_stream.read_to_string(&mut page).unwrap();
+        assert!(page.starts_with("HTTP/1.1 200 OK"));
+        assert!(page.contains("new WebSocket"));
+
+        // A connection that never sends its request holds up nobody else, even though the
+        // server waits 5 seconds for it
+        let _idle = TcpStream::connect(web_address).unwrap();
+        let joining = Instant::now();
+        let (mut ann, answer) = join(web_address, "ann");
+        assert!(matches!(answer, Message::Welcome { .. }));
+        let (bo, answer) = join(web_address, "bo");
+        assert!(matches!(answer, Message::Welcome { .. }));
+        let (_, answer) = join(web_address, "bo");
+        assert!(matches!(answer, Message::Refused(Refusal::NameTaken)));
+        assert!(joining.elapsed() < Duration::from_secs(4));
+
+        send(&mut ann, &Message::Ready);
+        server.listener_thread.take().unwrap().join().unwrap();
+
+        let bots: Vec<_> = [(ann, "ann"), (bo, "bo")]
+            .into_iter()
+            .map(|(socket, name)| thread::spawn(move || play_as_bot(socket, name)))
+            .collect();
+
+        let players: VecDeque<Player> = server
+            .player_network
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(player, _, _)| player.clone())
+            .collect();
+        let mut composition = Composition::new();
+        composition.num_decks = composition.decks_for(players.len());
+        let rules = RuleSet::new();
+        let match_rules = MatchRules {
+            goal: MatchGoal::Hands(1),
+            ..MatchRules::new()
+        };
+        let mut game = GameServer::new(
+            server,
+            players,
+            Deck::with_composition(composition),
+            logger,
+            rules,
+            match_rules,
+        );
+        game.start_game();
+
+        for bot in bots {
+            let mut standings = bot.join().unwrap();
+            standings.sort();
+            assert_eq!(standings, vec!["ann".to_string(), "bo".to_string()]);
+        }
+    }
 }
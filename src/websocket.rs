@@ -0,0 +1,344 @@
+use base64::Engine;
+use sha1::{Digest, Sha1};
+use std::io::{self, Read, Write};
+
+// Just enough of WebSocket (RFC 6455) for the server side of a browser table: the HTTP
+// upgrade, and text frames in both directions. The same listener also serves the page
+// browsers join from.
+
+const PAGE: &str = include_str!("web/index.html");
+
+// Appended to the client's key before hashing it, as the RFC asks
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// Longest HTTP request head and WebSocket message the server accepts
+const MAX_REQUEST_LENGTH: usize = 8 * 1024;
+const MAX_MESSAGE_LENGTH: u64 = 64 * 1024;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+// What a request to the web listener turned out to be
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    WebSocket,         // Upgraded, messages follow as WebSocket frames
+    Served,            // Answered with the page or an error, and done with
+    Forbidden(String), // An upgrade from a page on another site, given by its origin
+}
+
+// Read an HTTP request and either upgrade it to a WebSocket or answer it
+pub fn accept<S>(stream: &mut S) -> io::Result<Request>
+where
+    S: Read + Write,
+{
+    let head = read_request_head(stream)?;
+    let mut lines = head.lines();
+    let request_line = lines.next().unwrap_or_default();
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+
+    let mut upgrade = false;
+    let mut key = None;
+    let mut host = None;
+    let mut origin = None;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            match name.trim().to_lowercase().as_str() {
+                "upgrade" => upgrade = value.eq_ignore_ascii_case("websocket"),
+                "sec-websocket-key" => key = Some(value.to_string()),
+                "host" => host = Some(value.to_string()),
+                "origin" => origin = Some(value.to_string()),
+                _ => {}
+            }
+        }
+    }
+
+    match (upgrade, key) {
+        (true, Some(_)) if !is_allowed_origin(origin.as_deref(), host.as_deref()) => {
+            respond(stream, "403 Forbidden", "text/plain", "Forbidden")?;
+            Ok(Request::Forbidden(origin.unwrap_or_default()))
+        }
+        (true, Some(key)) => {
+            write!(
+                stream,
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(&key)
+            )?;
+            stream.flush()?;
+            Ok(Request::WebSocket)
+        }
+        _ if path == "/" || path == "/index.html" => {
+            respond(stream, "200 OK", "text/html; charset=utf-8", PAGE)?;
+            Ok(Request::Served)
+        }
+        _ => {
+            respond(stream, "404 Not Found", "text/plain", "Not found")?;
+            Ok(Request::Served)
+        }
+    }
+}
+
+// Browsers send the page's origin with every upgrade, so pages on other sites cannot join
+// through the player's browser. Only the page served here is let in, and only on a loopback
+// address, so a site whose name is pointed at 127.0.0.1 is turned away too. Clients that are
+// not browsers send no origin.
+fn is_allowed_origin(origin: Option<&str>, host: Option<&str>) -> bool {
+    let Some(origin) = origin else {
+        return true;
+    };
+    let Some(host) = host else {
+        return false;
+    };
+    let hostname = match host.rsplit_once(':') {
+        Some((hostname, port)) if !port.contains(']') => hostname,
+        _ => host,
+    };
+    ["localhost", "127.0.0.1", "[::1]"].contains(&hostname) && origin == format!("http://{host}")
+}
+
+// The key the server answers an upgrade with, proving it read the client's key
+pub fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(ACCEPT_GUID.as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(hasher.finalize())
+}
+
+// Reads one byte at a time, so that nothing past the blank line is taken from the stream
+fn read_request_head<R>(reader: &mut R) -> io::Result<String>
+where
+    R: Read,
+{
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_REQUEST_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "HTTP request too long",
+            ));
+        }
+        reader.read_exact(&mut byte)?;
+        head.push(byte[0]);
+    }
+    String::from_utf8(head)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "HTTP request is not UTF-8"))
+}
+
+fn respond<W>(writer: &mut W, status: &str, content_type: &str, body: &str) -> io::Result<()>
+where
+    W: Write,
+{
+    write!(
+        writer,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    writer.flush()
+}
+
+// Send a text message in a single unmasked frame, as servers do
+pub fn write_text<W>(writer: &mut W, text: &str) -> io::Result<()>
+where
+    W: Write,
+{
+    write_frame(writer, OPCODE_TEXT, text.as_bytes())
+}
+
+fn write_frame<W>(writer: &mut W, opcode: u8, payload: &[u8]) -> io::Result<()>
+where
+    W: Write,
+{
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        length if length < 126 => frame.push(length as u8),
+        length if length <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+// Read the next text message, joining fragmented frames. Pings are answered with a pong on
+// replies, pongs are skipped, and a close frame ends the connection.
+pub fn read_text<R, W>(reader: &mut R, replies: &mut W) -> io::Result<String>
+where
+    R: Read,
+    W: Write,
+{
+    let mut message = Vec::new();
+    loop {
+        let mut header = [0u8; 2];
+        reader.read_exact(&mut header)?;
+        let fin = header[0] & 0x80 != 0;
+        let opcode = header[0] & 0x0f;
+        let masked = header[1] & 0x80 != 0;
+
+        let length = match header[1] & 0x7f {
+            126 => {
+                let mut length = [0u8; 2];
+                reader.read_exact(&mut length)?;
+                u16::from_be_bytes(length) as u64
+            }
+            127 => {
+                let mut length = [0u8; 8];
+                reader.read_exact(&mut length)?;
+                u64::from_be_bytes(length)
+            }
+            length => length as u64,
+        };
+        if message.len() as u64 + length > MAX_MESSAGE_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "WebSocket message too long",
+            ));
+        }
+
+        let mut mask = [0u8; 4];
+        if masked {
+            reader.read_exact(&mut mask)?;
+        }
+        let mut payload = vec![0u8; length as usize];
+        reader.read_exact(&mut payload)?;
+        if masked {
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+        }
+
+        match opcode {
+            OPCODE_TEXT | OPCODE_BINARY | OPCODE_CONTINUATION => {
+                message.extend_from_slice(&payload);
+                if fin {
+                    break;
+                }
+            }
+            OPCODE_CLOSE => {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "WebSocket closed",
+                ))
+            }
+            OPCODE_PING => write_frame(replies, OPCODE_PONG, &payload)?,
+            _ => {}
+        }
+    }
+
+    String::from_utf8(message)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "message is not valid UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_key_matches_the_rfc_example() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    // Answers are written to a buffer, as if to the browser
+    struct MockStream {
+        request: io::Cursor<Vec<u8>>,
+        answer: Vec<u8>,
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.request.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.answer.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn upgrade(headers: &str) -> (Request, String) {
+        let request = format!(
+            "GET / HTTP/1.1\r\nUpgrade: websocket\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n{headers}\r\n"
+        );
+        let mut stream = MockStream {
+            request: io::Cursor::new(request.into_bytes()),
+            answer: Vec::new(),
+        };
+        let request = accept(&mut stream).unwrap();
+        (request, String::from_utf8(stream.answer).unwrap())
+    }
+
+    #[test]
+    fn upgrades_from_other_sites_are_forbidden() {
+        let (request, answer) =
+            upgrade("Host: 127.0.0.1:9142\r\nOrigin: http://127.0.0.1:9142\r\n");
+        assert_eq!(request, Request::WebSocket);
+        assert!(answer.starts_with("HTTP/1.1 101"));
+
+        // Clients that are not browsers send no origin
+        let (request, _) = upgrade("Host: localhost:9142\r\n");
+        assert_eq!(request, Request::WebSocket);
+
+        let (request, answer) = upgrade("Host: 127.0.0.1:9142\r\nOrigin: http://evil.example\r\n");
+        assert_eq!(
+            request,
+            Request::Forbidden("http://evil.example".to_string())
+        );
+        assert!(answer.starts_with("HTTP/1.1 403"));
+
+        // A site pointing its own name at 127.0.0.1
+        let (request, _) =
+            upgrade("Host: evil.example:9142\r\nOrigin: http://evil.example:9142\r\n");
+        assert_eq!(
+            request,
+            Request::Forbidden("http://evil.example:9142".to_string())
+        );
+    }
+
+    #[test]
+    fn masked_frames_are_unmasked() {
+        // A masked "Hello" from the RFC
+        let frame = [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        assert_eq!(
+            read_text(&mut frame.as_slice(), &mut io::sink()).unwrap(),
+            "Hello"
+        );
+
+        let mut written = Vec::new();
+        write_text(&mut written, "Hello").unwrap();
+        assert_eq!(written, [0x81, 0x05, b'H', b'e', b'l', b'l', b'o']);
+    }
+
+    #[test]
+    fn pings_are_answered_with_pongs() {
+        // A ping carrying "hi", then "Hello"
+        let frames = [
+            0x89, 0x02, b'h', b'i', 0x81, 0x05, b'H', b'e', b'l', b'l', b'o',
+        ];
+        let mut replies = Vec::new();
+        assert_eq!(
+            read_text(&mut frames.as_slice(), &mut replies).unwrap(),
+            "Hello"
+        );
+        assert_eq!(replies, [0x8a, 0x02, b'h', b'i']);
+    }
+}